@@ -1,5 +1,7 @@
 // Shared utilities for embedding generators
 use rand::Rng;
+use serde::Serialize;
+use std::io::Write;
 use rand_distr::{Distribution, Normal};
 
 /// Generate a random normalized embedding vector
@@ -24,24 +26,126 @@ pub fn normalize(vec: &mut [f64]) {
     }
 }
 
-/// Generate a cluster of embeddings around a center point
+/// Settings for `generate_cluster_with`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClusterOptions {
+    /// Standard deviation of the Gaussian noise added to each component.
+    pub noise: f64,
+    /// Clamp each noisy component into `(min, max)` before normalizing; `None`
+    /// keeps the raw values. Clamping flattens clusters whose center sits near the
+    /// bounds, so disable it for more realistic spreads.
+    pub clamp: Option<(f64, f64)>,
+    /// Scale every point to unit length.
+    pub normalize: bool,
+}
+
+impl Default for ClusterOptions {
+    /// The behavior of `generate_cluster`: clamp to `[-1, 1]`, then normalize.
+    fn default() -> Self {
+        Self { noise: 0.1, clamp: Some((-1.0, 1.0)), normalize: true }
+    }
+}
+
+/// Generate a cluster of embeddings around a center point (components clamped
+/// to `[-1, 1]`, then normalized); see `generate_cluster_with` for other settings.
 pub fn generate_cluster(
     center: &[f64],
     noise: f64,
     count: usize,
     rng: &mut impl Rng,
 ) -> Vec<Vec<f64>> {
-    let normal = Normal::new(0.0, noise).unwrap();
+    generate_cluster_with(center, count, &ClusterOptions { noise, ..Default::default() }, rng)
+}
+
+/// Generate `count` points around `center` as configured by `opts`. Results are
+/// reproducible for a seeded `rng` (e.g. `ChaCha8Rng::seed_from_u64`).
+///
+/// # Panics
+/// If `opts.noise` is negative or not finite.
+pub fn generate_cluster_with(
+    center: &[f64],
+    count: usize,
+    opts: &ClusterOptions,
+    rng: &mut impl Rng,
+) -> Vec<Vec<f64>> {
+    let normal = Normal::new(0.0, opts.noise).unwrap();
     let mut embeddings = Vec::with_capacity(count);
     
     for _ in 0..count {
         let mut vec: Vec<f64> = center
             .iter()
-            .map(|&c| (c + normal.sample(rng)).clamp(-1.0, 1.0))
+            .map(|&c| {
+                let v = c + normal.sample(rng);
+                match opts.clamp {
+                    Some((lo, hi)) => v.clamp(lo, hi),
+                    None => v,
+                }
+            })
             .collect();
-        normalize(&mut vec);
+        if opts.normalize {
+            normalize(&mut vec);
+        }
         embeddings.push(vec);
     }
     
     embeddings
 }
+
+/// Write `embeddings` as JSON lines (`{"id":..,"vector":[..]}`), the input format
+/// `vectro_cli compress` reads. Components are written with f32 precision.
+/// Returns the number of lines written.
+pub fn write_jsonl<W: Write>(embeddings: &[(String, Vec<f32>)], mut writer: W) -> std::io::Result<usize> {
+    #[derive(Serialize)]
+    struct Line<'a> {
+        id: &'a str,
+        vector: &'a [f32],
+    }
+
+    for (id, vector) in embeddings {
+        serde_json::to_writer(&mut writer, &Line { id, vector })?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(embeddings.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn cluster_without_clamping_exceeds_old_bounds() {
+        let center = vec![0.95, -0.95, 0.0];
+        let raw = ClusterOptions { noise: 0.3, clamp: None, normalize: false };
+        let points = generate_cluster_with(&center, 200, &raw, &mut ChaCha8Rng::seed_from_u64(7));
+        assert!(points.iter().flatten().any(|x| x.abs() > 1.0));
+
+        let clamped = ClusterOptions { clamp: Some((-1.0, 1.0)), ..raw };
+        let points = generate_cluster_with(&center, 200, &clamped, &mut ChaCha8Rng::seed_from_u64(7));
+        assert!(points.iter().flatten().all(|x| x.abs() <= 1.0));
+
+        // the defaults reproduce `generate_cluster` exactly
+        let a = generate_cluster(&center, 0.3, 5, &mut ChaCha8Rng::seed_from_u64(1));
+        let opts = ClusterOptions { noise: 0.3, ..Default::default() };
+        let b = generate_cluster_with(&center, 5, &opts, &mut ChaCha8Rng::seed_from_u64(1));
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn write_jsonl_is_compress_input() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let embeddings: Vec<(String, Vec<f32>)> = (0..25)
+            .map(|i| (format!("doc_{i}"), generate_embedding(8, &mut rng).into_iter().map(|x| x as f32).collect()))
+            .collect();
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.jsonl");
+        let written = write_jsonl(&embeddings, std::fs::File::create(&input).unwrap()).unwrap();
+        assert_eq!(written, 25);
+
+        let output = dir.path().join("out.bin");
+        let count = vectro_cli::compress_stream(input.to_str().unwrap(), output.to_str().unwrap(), false).unwrap();
+        assert_eq!(count, 25);
+    }
+}
@@ -88,11 +88,87 @@ class TestVectroPlusCore(unittest.TestCase):
         for i, (indices, similarities) in enumerate(results):
             self.assertEqual(len(indices), 3)
             self.assertEqual(len(similarities), 3)
+            self.assertEqual(indices.dtype, np.int64)
             
             # First result should be the exact match
             self.assertEqual(indices[0], i)
             self.assertAlmostEqual(similarities[0], 1.0, places=5)
     
+    def test_search_vector_dtype(self):
+        """Test score dtype selection and int64 indices."""
+        index = vp.create_index(self.vectors, self.ids)
+        query = self.vectors[0].copy()
+        
+        indices, scores = index.search_vector(query, 5)
+        self.assertEqual(indices.dtype, np.int64)
+        self.assertEqual(scores.dtype, np.float32)
+        
+        indices64, scores64 = index.search_vector(query, 5, dtype="float64")
+        self.assertEqual(scores64.dtype, np.float64)
+        np.testing.assert_array_equal(indices, indices64)
+        np.testing.assert_allclose(scores, scores64, rtol=1e-6)
+        
+        with self.assertRaises(ValueError):
+            index.search_vector(query, 5, dtype="int8")
+    
+    def test_batch_search_transposed(self):
+        """Test batch search over column-major queries."""
+        index = vp.create_index(self.vectors, self.ids)
+        
+        queries = self.vectors[:4].copy()
+        row_major = index.batch_search(queries, 3)
+        col_major = index.batch_search(np.asfortranarray(queries.T), 3, transpose=True)
+        
+        self.assertEqual(len(col_major), 4)
+        for (ri, rs), (ci, cs) in zip(row_major, col_major):
+            np.testing.assert_array_equal(ri, ci)
+            np.testing.assert_allclose(rs, cs)
+        
+        # dimension is checked on the query axis
+        with self.assertRaises(ValueError):
+            index.batch_search(queries, 3, transpose=True)
+    
+    def test_euclidean_metric(self):
+        """Test that a Euclidean index ranks by L2 distance."""
+        vectors = np.array([[0.0, 0.0], [10.0, 0.0], [2.0, 2.0]], dtype=np.float32)
+        index = vp.create_index(vectors, metric="euclidean")
+        self.assertEqual(index.metric, "euclidean")
+        
+        # (2, 2) is nearest in L2, although (10, 0) points closer in angle
+        query = np.array([3.0, 1.0], dtype=np.float32)
+        indices, distances = index.search_vector(query, 3)
+        expected = np.argsort(np.linalg.norm(vectors - query, axis=1))
+        np.testing.assert_array_equal(indices, expected)
+        self.assertAlmostEqual(distances[0], np.sqrt(2.0), places=5)
+        
+        with self.assertRaises(ValueError):
+            vp.create_index(vectors, metric="manhattan")
+    
+    def test_normalized_vectors(self):
+        """Test that the index exposes its unit-length stored vectors."""
+        vectors = self.vectors * 5.0
+        index = vp.create_index(vectors, self.ids)
+        normalized = index.normalized_vectors()
+        self.assertEqual(normalized.shape, vectors.shape)
+        np.testing.assert_allclose(np.linalg.norm(normalized, axis=1), 1.0, atol=1e-5)
+        np.testing.assert_allclose(normalized, self.vectors, atol=1e-5)
+    
+    def test_normalized_vectors_keep_dataset_rows(self):
+        """Rows line up with result indices even when a vector is skipped."""
+        dataset = vp.EmbeddingDataset()
+        dataset.add_vector("a", np.array([3.0, 4.0], dtype=np.float32))
+        dataset.add_vector("odd", np.array([1.0, 0.0, 0.0], dtype=np.float32))
+        dataset.add_vector("b", np.array([0.0, 2.0], dtype=np.float32))
+        index = vp.SearchIndex.from_dataset(dataset)
+        self.assertEqual(index.skipped, ["odd"])
+        
+        normalized = index.normalized_vectors()
+        self.assertEqual(normalized.shape, (3, 2))
+        np.testing.assert_allclose(normalized[1], [0.0, 0.0])
+        indices, _ = index.search_vector(np.array([0.0, 1.0], dtype=np.float32), 1)
+        self.assertEqual(indices[0], 2)
+        np.testing.assert_allclose(normalized[indices[0]], [0.0, 1.0], atol=1e-6)
+    
     def test_quantized_search_quality(self):
         """Test that quantized search maintains reasonable quality."""
         regular_index = vp.create_index(self.vectors, self.ids)
@@ -128,6 +204,7 @@ class TestVectroPlusCore(unittest.TestCase):
         q_indices, q_similarities = quantized_index.search_vector(query, top_k=3)
         self.assertEqual(len(q_indices), 3)
         self.assertEqual(q_indices[0], 0)
+        self.assertEqual(q_indices.dtype, np.int64)
 
 
 class TestQualityAnalysis(unittest.TestCase):
@@ -203,6 +280,17 @@ class TestQualityAnalysis(unittest.TestCase):
         self.assertGreater(benchmark["queries_per_second"], 0)
         self.assertEqual(benchmark["total_runs"], 30)  # 10 queries * 3 runs
 
+    def test_benchmark_quantized_search_performance(self):
+        """Test quantized search performance benchmarking."""
+        queries = np.random.randn(8, 32).astype(np.float32)
+        
+        benchmark = vp.benchmark_quantized_search_performance(
+            self.quantized_index, queries, top_k=5, num_runs=2
+        )
+        
+        self.assertGreater(benchmark["queries_per_second"], 0)
+        self.assertEqual(benchmark["total_runs"], 16)  # 8 queries * 2 runs
+
 
 class TestErrorHandling(unittest.TestCase):
     """Test error handling and edge cases."""
@@ -272,6 +360,24 @@ class TestUtilities(unittest.TestCase):
         self.assertIsInstance(dataset, vp.EmbeddingDataset)
         self.assertEqual(len(dataset), 20)
     
+    def test_quantize_dequantize_matrix(self):
+        """Test raw matrix quantization round trip."""
+        vectors = np.random.randn(50, 8).astype(np.float32)
+
+        tables, codes = vp.quantize_matrix(vectors)
+        self.assertEqual(len(tables), 8)
+        self.assertEqual(codes.shape, (50, 8))
+        self.assertEqual(codes.dtype, np.uint8)
+
+        restored = vp.dequantize_matrix(tables, codes)
+        self.assertEqual(restored.shape, vectors.shape)
+        # error per component is at most half a quantization step
+        steps = np.array([(mx - mn) / 255.0 for mn, mx in tables], dtype=np.float32)
+        self.assertTrue(np.all(np.abs(restored - vectors) <= steps / 2 + 1e-5))
+
+        with self.assertRaises(ValueError):
+            vp.dequantize_matrix(tables[:4], codes)
+    
     def test_package_info(self):
         """Test package information functions."""
         # Test info function
@@ -41,6 +41,9 @@ try:
         compress_embeddings,
         analyze_compression_quality,
         benchmark_search_performance,
+        benchmark_quantized_search_performance,
+        quantize_matrix,
+        dequantize_matrix,
         __version__,
         __author__,
         __description__,
@@ -70,7 +73,10 @@ __all__ = [
     # Analysis functions
     "analyze_compression_quality",
     "benchmark_search_performance",
+    "benchmark_quantized_search_performance",
     "generate_quality_report",
+    "quantize_matrix",
+    "dequantize_matrix",
     # Utility functions
     "load_embeddings_from_array",
     "save_index",
@@ -107,7 +113,8 @@ class VectroConfig:
 
 def create_index(vectors: np.ndarray, 
                 ids: Optional[List[str]] = None,
-                config: Optional[VectroConfig] = None) -> SearchIndex:
+                config: Optional[VectroConfig] = None,
+                metric: str = "cosine") -> SearchIndex:
     """
     Create a search index from vectors.
     
@@ -115,6 +122,8 @@ def create_index(vectors: np.ndarray,
         vectors: Array of shape (n_vectors, n_dimensions) 
         ids: Optional list of string IDs for vectors
         config: Optional configuration object
+        metric: "cosine", "euclidean" (scores are L2 distances, nearest
+            first) or "dot"
         
     Returns:
         SearchIndex object for performing searches
@@ -138,7 +147,7 @@ def create_index(vectors: np.ndarray,
         dataset.add_vector(vector_id, vector.astype(np.float32))
     
     # Create index
-    return SearchIndex.from_dataset(dataset)
+    return SearchIndex.from_dataset(dataset, metric=metric)
 
 
 def create_quantized_index(vectors: np.ndarray,
@@ -207,14 +216,18 @@ def search_similar(index: Union[SearchIndex, QuantizedIndex],
 
 def batch_search(index: Union[SearchIndex, QuantizedIndex],
                 queries: np.ndarray,
-                top_k: int = 10) -> List[Tuple[np.ndarray, np.ndarray]]:
+                top_k: int = 10,
+                transpose: bool = False) -> List[Tuple[np.ndarray, np.ndarray]]:
     """
     Perform batch search for multiple queries.
     
     Args:
         index: SearchIndex or QuantizedIndex to search
-        queries: Array of shape (n_queries, n_dimensions)
+        queries: Array of shape (n_queries, n_dimensions), or
+            (n_dimensions, n_queries) when ``transpose`` is True
         top_k: Number of top results to return per query
+        transpose: Treat columns as queries (SearchIndex only); avoids copying
+            column-major query matrices
         
     Returns:
         List of (indices, similarities) tuples, one per query
@@ -228,6 +241,8 @@ def batch_search(index: Union[SearchIndex, QuantizedIndex],
     if not _rust_available:
         raise RuntimeError("Rust extension not available. Please install properly.")
         
+    if transpose:
+        return index.batch_search(queries.astype(np.float32, copy=False), top_k, transpose=True)
     return index.batch_search(queries.astype(np.float32), top_k)
 
 
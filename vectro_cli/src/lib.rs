@@ -1,12 +1,276 @@
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, BufReader, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use indicatif::{ProgressBar, ProgressStyle};
 
+/// How long-running commands report progress on stderr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// Animated spinner; only sensible on an interactive terminal.
+    Spinner,
+    /// Plain status lines, at most one per second, for logs and pipes.
+    Plain,
+    /// No progress output at all (`--quiet`).
+    Silent,
+}
+
+/// Pick the progress style: `quiet` wins, otherwise spinner on a TTY and plain lines elsewhere.
+pub fn choose_progress_mode(is_tty: bool, quiet: bool) -> ProgressMode {
+    match (quiet, is_tty) {
+        (true, _) => ProgressMode::Silent,
+        (false, true) => ProgressMode::Spinner,
+        (false, false) => ProgressMode::Plain,
+    }
+}
+
+/// `choose_progress_mode` for the current process's stderr.
+pub fn detect_progress_mode(quiet: bool) -> ProgressMode {
+    choose_progress_mode(std::io::stderr().is_terminal(), quiet)
+}
+
+/// Progress reporter backing all three `ProgressMode`s. `println` output (warnings,
+/// streamed child output) is always shown; only status messages depend on the mode.
+#[derive(Clone)]
+pub struct Progress {
+    mode: ProgressMode,
+    bar: ProgressBar,
+    last_plain: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Progress {
+    pub fn new(mode: ProgressMode) -> Self {
+        let bar = match mode {
+            ProgressMode::Spinner => {
+                let pb = ProgressBar::new_spinner();
+                pb.set_style(ProgressStyle::with_template("{spinner} {msg}").unwrap());
+                pb.enable_steady_tick(Duration::from_millis(80));
+                pb
+            }
+            ProgressMode::Plain | ProgressMode::Silent => ProgressBar::hidden(),
+        };
+        Progress { mode, bar, last_plain: Arc::new(Mutex::new(None)) }
+    }
+
+    pub fn mode(&self) -> ProgressMode {
+        self.mode
+    }
+
+    pub fn set_message(&self, msg: impl Into<String>) {
+        match self.mode {
+            ProgressMode::Spinner => self.bar.set_message(msg.into()),
+            ProgressMode::Plain => {
+                let mut last = self.last_plain.lock().unwrap();
+                if last.is_none_or(|t| t.elapsed() >= Duration::from_secs(1)) {
+                    eprintln!("{}", msg.into());
+                    *last = Some(Instant::now());
+                }
+            }
+            ProgressMode::Silent => {}
+        }
+    }
+
+    pub fn println(&self, line: impl AsRef<str>) {
+        match self.mode {
+            ProgressMode::Spinner => self.bar.println(line),
+            ProgressMode::Plain | ProgressMode::Silent => eprintln!("{}", line.as_ref()),
+        }
+    }
+
+    pub fn finish_and_clear(&self) {
+        self.bar.finish_and_clear();
+    }
+
+    /// Final status line; unlike `set_message` it is never throttled in plain mode.
+    pub fn finish_with_message(&self, msg: impl Into<String>) {
+        match self.mode {
+            ProgressMode::Spinner => self.bar.finish_with_message(msg.into()),
+            ProgressMode::Plain => eprintln!("{}", msg.into()),
+            ProgressMode::Silent => {}
+        }
+    }
+}
+
+/// Roughly how many count-based progress updates a run gets.
+const PROGRESS_UPDATES: usize = 20;
+
+/// Least time between progress updates once the count-based step hasn't been met.
+const PROGRESS_MIN_GAP: Duration = Duration::from_millis(100);
+
+/// Records between count-based progress updates for about `estimated_total`
+/// records: `PROGRESS_UPDATES` steps over the run, never less than one record.
+fn progress_interval(estimated_total: usize) -> usize {
+    estimated_total.div_ceil(PROGRESS_UPDATES).max(1)
+}
+
+/// Decides when a counting loop should refresh its progress message: every
+/// `progress_interval` records, or when `PROGRESS_MIN_GAP` has passed since the
+/// last update so runs longer than the estimate keep moving.
+struct ProgressCadence {
+    every: usize,
+    last: Instant,
+}
+
+impl ProgressCadence {
+    fn new(estimated_total: usize) -> Self {
+        Self { every: progress_interval(estimated_total), last: Instant::now() }
+    }
+
+    /// Estimate the record count of a `file_len`-byte input from the length of its
+    /// first line (plus its newline).
+    fn for_input(file_len: u64, first_line_len: usize) -> Self {
+        Self::new((file_len / (first_line_len as u64 + 1)) as usize)
+    }
+
+    /// True when the message should be refreshed after `count` records.
+    fn should_update(&mut self, count: usize) -> bool {
+        if count.is_multiple_of(self.every) || self.last.elapsed() >= PROGRESS_MIN_GAP {
+            self.last = Instant::now();
+            return true;
+        }
+        false
+    }
+}
+
+/// Options for `compress_with_options`. `Default` matches the plain
+/// `compress_stream(input, output, false)` behavior.
+#[derive(Debug, Clone, Default)]
+pub struct CompressOptions {
+    /// Produce a quantized `VECTRO+QSTREAM3` stream instead of plain records.
+    pub quantize: bool,
+    /// Normalize every vector to unit length before it is stored (and, when
+    /// quantizing, before the tables are computed; recorded in the stream's flags).
+    /// Zero vectors are kept as is with a warning. This is lossy: the original
+    /// magnitudes are not kept, so raw dot products cannot be recovered later.
+    pub normalize: bool,
+    /// Metric the quantized stream is meant to be searched with, recorded in its
+    /// flags so `QuantizedIndex::load_stream` scores with it.
+    pub metric: vectro_lib::search::quant::StreamMetric,
+    /// Reject a whole record when any component fails to parse (or is non-finite),
+    /// or when its length differs from the first accepted record. When false,
+    /// unparseable components are dropped and the shorter vector is kept.
+    pub strict_parse: bool,
+    /// Stop reading after this many successfully parsed records.
+    pub limit: Option<usize>,
+    /// Progress style; `None` detects it from whether stderr is a terminal.
+    pub progress: Option<ProgressMode>,
+    /// Flush the output every this many records so a crash loses at most that many
+    /// (at some throughput cost). `None` flushes only at the end. A quantized stream
+    /// cut short still fails to load, since its checksum footer is written last.
+    pub flush_every: Option<usize>,
+}
+
+/// Parse one input line as JSON (`{"id":..,"vector":[..]}`) or CSV (`id,v1,v2,..`).
+/// Returns `Ok(None)` for lines that are not records at all; in strict mode a record
+/// with a bad component is an `Err` carrying the reason.
+fn parse_record(line: &str, strict: bool) -> Result<Option<vectro_lib::Embedding>, String> {
+    if let Ok(val) = serde_json::from_str::<serde_json::Value>(line) {
+        if let (Some(id), Some(vec)) = (val.get("id"), val.get("vector")) {
+            if let (Some(id_str), Some(arr)) = (id.as_str(), vec.as_array()) {
+                let mut v = Vec::with_capacity(arr.len());
+                for (i, x) in arr.iter().enumerate() {
+                    match x.as_f64() {
+                        Some(flt) if !strict || (flt as f32).is_finite() => v.push(flt as f32),
+                        _ if strict => return Err(format!("component {} ({}) is not a finite number", i, x)),
+                        _ => {}
+                    }
+                }
+                return Ok(Some(vectro_lib::Embedding::new(id_str, v)));
+            }
+        }
+    }
+    // CSV
+    let parts: Vec<&str> = line.split(',').collect();
+    if parts.len() < 2 {
+        return Ok(None);
+    }
+    let mut v = Vec::with_capacity(parts.len() - 1);
+    for (i, p) in parts[1..].iter().enumerate() {
+        match p.trim().parse::<f32>() {
+            Ok(f) if !strict || f.is_finite() => v.push(f),
+            _ if strict => return Err(format!("component {} ({:?}) is not a finite number", i, p.trim())),
+            _ => {}
+        }
+    }
+    Ok(Some(vectro_lib::Embedding::new(parts[0], v)))
+}
+
+/// Result of `compress_with_outcome`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompressOutcome {
+    /// Records written to the output.
+    pub written: usize,
+    /// `(line_number, raw_line)` for every non-empty input line that was not written:
+    /// lines that are not a JSON or CSV record, plus strict-mode rejections. Line
+    /// numbers are 1-based.
+    pub rejected: Vec<(usize, String)>,
+    /// True when a `CancelToken` stopped the run before the end of the input.
+    pub cancelled: bool,
+}
+
+/// Shared flag for stopping `compress_stream_cancellable` from another thread.
+/// Clones share the flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask the operation to stop; it finishes the records already read first.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Warn on stderr about every embedding `idx` left out for its dimension.
+pub fn warn_skipped(idx: &vectro_lib::search::SearchIndex) {
+    for id in idx.skipped() {
+        eprintln!("warning: skipped embedding {:?}: its dimension does not match the index dimension {}", id, idx.dim());
+    }
+}
+
 pub fn compress_stream(input: &str, output: &str, quantize: bool) -> anyhow::Result<usize> {
+    compress_with_options(input, output, &CompressOptions { quantize, ..Default::default() })
+}
+
+/// Like `compress_with_outcome`, returning only the number of records written.
+pub fn compress_with_options(input: &str, output: &str, opts: &CompressOptions) -> anyhow::Result<usize> {
+    compress_with_outcome(input, output, opts).map(|o| o.written)
+}
+
+/// Compress `input` into `output` and report which lines were rejected.
+pub fn compress_with_outcome(input: &str, output: &str, opts: &CompressOptions) -> anyhow::Result<CompressOutcome> {
+    compress_inner(input, output, opts, None)
+}
+
+/// `compress_with_outcome` that stops reading input once `cancel` is set. Records
+/// read before that are still encoded and written, and the output is finalized
+/// (a quantized stream gets tables for the partial data and its checksum footer),
+/// so a cancelled run leaves a smaller file that loads normally.
+pub fn compress_stream_cancellable(
+    input: &str,
+    output: &str,
+    opts: &CompressOptions,
+    cancel: &CancelToken,
+) -> anyhow::Result<CompressOutcome> {
+    compress_inner(input, output, opts, Some(cancel))
+}
+
+fn compress_inner(input: &str, output: &str, opts: &CompressOptions, cancel: Option<&CancelToken>) -> anyhow::Result<CompressOutcome> {
     use crossbeam_channel::{bounded, Sender, Receiver};
     use std::thread;
 
-    let header = b"VECTRO+STREAM1\n";
+    let quantize = opts.quantize;
+    // plain streams use the codec selected by vectro_lib's `postcard` feature
+    let header = vectro_lib::codec::stream_header();
     let infile = std::fs::File::open(input)?;
+    let input_len = infile.metadata().map(|m| m.len()).unwrap_or(0);
     let reader = BufReader::new(infile);
 
     let outfile = std::fs::File::create(output)?;
@@ -17,27 +281,28 @@ pub fn compress_stream(input: &str, output: &str, quantize: bool) -> anyhow::Res
     let (bytes_tx, bytes_rx): (Sender<Vec<u8>>, Receiver<Vec<u8>>) = bounded(1024);
 
     // writer thread (non-quantized path will spawn writer now; quantized path spawns writer after tables computed)
-    let out_clone = output.to_string();
-    let qheader = b"VECTRO+QSTREAM1\n";
+    let qheader = vectro_lib::QSTREAM_HEADER_V3;
+    let flags = vectro_lib::search::quant::StreamFlags { normalized: opts.normalize, metric: opts.metric };
     let mut writer_handle_opt = None;
     // prepare worker handles container
     let mut worker_handles: Vec<std::thread::JoinHandle<()>> = Vec::new();
     if !quantize {
         let mut w = writer_buf;
         let rx_for_writer = bytes_rx.clone();
-        let out_for_writer = out_clone.clone();
-        let header_local = *header;
+        let header_local = header;
+        let flush_every = opts.flush_every;
         let handle = thread::spawn(move || -> anyhow::Result<()> {
-            w.write_all(&header_local)?;
+            w.write_all(header_local)?;
             let mut written = 0usize;
             while let Ok(bytes) = rx_for_writer.recv() {
-                let len = (bytes.len() as u32).to_le_bytes();
+                let len = vectro_lib::codec::record_len_prefix(bytes.len())?;
                 w.write_all(&len)?;
                 w.write_all(&bytes)?;
                 written += 1;
+                if flush_every.is_some_and(|n| n > 0 && written.is_multiple_of(n)) { w.flush()?; }
             }
+            // the summary is the progress reporter's finish message
             w.flush()?;
-            eprintln!("wrote {} entries to {}", written, out_for_writer);
             Ok(())
         });
         writer_handle_opt = Some(handle);
@@ -48,7 +313,7 @@ pub fn compress_stream(input: &str, output: &str, quantize: bool) -> anyhow::Res
             let tx = bytes_tx.clone();
             worker_handles.push(thread::spawn(move || {
                 while let Ok(e) = r.recv() {
-                    if let Ok(bytes) = bincode::serialize(&e) {
+                    if let Ok(bytes) = vectro_lib::codec::encode(&e) {
                         let _ = tx.send(bytes);
                     }
                 }
@@ -59,91 +324,135 @@ pub fn compress_stream(input: &str, output: &str, quantize: bool) -> anyhow::Res
     // don't spawn workers yet; will spawn depending on quantize mode
 
     // progress bar
-    let pb = ProgressBar::new_spinner();
-    pb.set_style(ProgressStyle::with_template("{spinner} {msg}").unwrap());
-    pb.enable_steady_tick(std::time::Duration::from_millis(80));
+    let pb = Progress::new(opts.progress.unwrap_or_else(|| detect_progress_mode(false)));
     if quantize {
         pb.set_message("parsing and computing quant tables...");
     } else {
-        pb.set_message("compressing (streaming bincode)...");
+        pb.set_message("compressing (streaming records)...");
     }
 
     // reader: parse lines and collect embeddings
     let mut parsed = 0usize;
+    // first accepted record fixes the expected dimension in strict mode
+    let mut expected_dim: Option<usize> = None;
     // collect embeddings when quantizing
     let mut collected_embeddings: Vec<vectro_lib::Embedding> = Vec::new();
-    for line in reader.lines().map_while(Result::ok) {
-        let line = line.trim();
+    let mut rejected: Vec<(usize, String)> = Vec::new();
+    let mut cancelled = false;
+    // sized from the first line once it has been read
+    let mut cadence: Option<ProgressCadence> = None;
+    for (lineno, raw) in reader.lines().map_while(Result::ok).enumerate() {
+        // stop early; channels are closed below exactly as on EOF so workers and writer drain
+        if opts.limit.is_some_and(|limit| parsed >= limit) { break; }
+        if cancel.is_some_and(CancelToken::is_cancelled) {
+            cancelled = true;
+            break;
+        }
+        let line = raw.trim();
         if line.is_empty() { continue; }
 
-        // try JSON
-        let mut pushed = false;
-        if let Ok(val) = serde_json::from_str::<serde_json::Value>(line) {
-            if let (Some(id), Some(vec)) = (val.get("id"), val.get("vector")) {
-                if let (Some(id_str), Some(arr)) = (id.as_str(), vec.as_array()) {
-                    let mut v = Vec::with_capacity(arr.len());
-                    for x in arr { if let Some(flt) = x.as_f64() { v.push(flt as f32); } }
-                    let emb = vectro_lib::Embedding::new(id_str, v.clone());
-                    if quantize { collected_embeddings.push(emb.clone()); } else { let _ = item_tx.send(emb); }
-                    parsed += 1;
-                    pushed = true;
-                }
+        let mut emb = match parse_record(line, opts.strict_parse) {
+            Ok(Some(emb)) => emb,
+            Ok(None) => {
+                rejected.push((lineno + 1, raw));
+                continue;
+            }
+            Err(reason) => {
+                pb.println(format!("rejected line {}: {}", lineno + 1, reason));
+                rejected.push((lineno + 1, raw));
+                continue;
+            }
+        };
+        if opts.strict_parse {
+            let dim = *expected_dim.get_or_insert(emb.vector.len());
+            if emb.vector.len() != dim {
+                pb.println(format!("rejected line {}: expected {} components, found {}", lineno + 1, dim, emb.vector.len()));
+                rejected.push((lineno + 1, raw));
+                continue;
             }
         }
-        if !pushed {
-            // CSV
-            let parts: Vec<&str> = line.split(',').collect();
-            if parts.len() >= 2 {
-                let id = parts[0].to_string();
-                let mut v = Vec::new();
-                for p in &parts[1..] { if let Ok(f) = p.trim().parse::<f32>() { v.push(f); } }
-                let emb = vectro_lib::Embedding::new(id, v.clone());
-                if quantize { collected_embeddings.push(emb.clone()); } else { let _ = item_tx.send(emb); }
-                parsed += 1;
+        if opts.normalize {
+            let n = emb.vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+            if n > 0.0 {
+                emb.vector.iter_mut().for_each(|x| *x /= n);
+            } else {
+                pb.println(format!("warning: line {} ({:?}) is a zero vector and was not normalized", lineno + 1, emb.id));
             }
         }
+        if quantize { collected_embeddings.push(emb); } else { let _ = item_tx.send(emb); }
+        parsed += 1;
 
-        if parsed % 100 == 0 { pb.set_message(format!("parsed {} entries", parsed)); }
+        let cadence = cadence.get_or_insert_with(|| ProgressCadence::for_input(input_len, raw.len()));
+        if cadence.should_update(parsed) { pb.set_message(format!("parsed {} entries", parsed)); }
     }
 
     if quantize {
-        // compute tables using vectro_lib::search::quant::quantize_dataset
-        let vectors: Vec<Vec<f32>> = collected_embeddings.iter().map(|e| e.vector.clone()).collect();
-        let (tables, _qvecs) = vectro_lib::search::quant::quantize_dataset(&vectors);
+        // compute tables using vectro_lib::search::quant::try_quantize_dataset
+        // split ids from vectors so the tables can be computed without copying every vector
+        // vectors were already normalized while reading when `normalize` is set
+        let (ids, vectors): (Vec<String>, Vec<Vec<f32>>) = collected_embeddings.into_iter().map(|e| (e.id, e.vector)).unzip();
+        // lenient parsing keeps short rows, which cannot share one set of tables
+        if let Some(i) = vectors.iter().position(|v| v.len() != vectors[0].len()) {
+            anyhow::bail!(
+                "cannot quantize {}: record {:?} has {} components, expected {} (--strict-parse rejects such lines)",
+                input,
+                ids[i],
+                vectors[i].len(),
+                vectors[0].len()
+            );
+        }
+        let (tables, _qvecs) = vectro_lib::search::quant::try_quantize_dataset(&vectors)?;
         // serialize tables to bincode
         let tables_blob = bincode::serialize(&tables)?;
 
+        // the footer checksum covers every byte before it, starting with the header
+        let mut hasher = crc32fast::Hasher::new();
+
         // write header + tables to file, then spawn writer thread to append entries
         {
             // overwrite/create file and write header+tables
-            let mut f = std::fs::File::create(output)?;
-            let mut w = std::io::BufWriter::new(&mut f);
-            w.write_all(qheader)?;
+            let mut head = Vec::with_capacity(qheader.len() + 13 + tables_blob.len());
+            head.extend_from_slice(qheader);
+            head.push(flags.to_byte());
             let table_count = (tables.len() as u32).to_le_bytes();
-            let dim = (if !tables.is_empty() { tables.len() as u32 } else { 0u32 }).to_le_bytes();
+            // original vector dimension, recorded independently of the table layout
+            let dim = (vectors.first().map(|v| v.len()).unwrap_or(0) as u32).to_le_bytes();
             let tables_len = (tables_blob.len() as u32).to_le_bytes();
-            w.write_all(&table_count)?;
-            w.write_all(&dim)?;
-            w.write_all(&tables_len)?;
-            w.write_all(&tables_blob)?;
+            head.extend_from_slice(&table_count);
+            head.extend_from_slice(&dim);
+            head.extend_from_slice(&tables_len);
+            head.extend_from_slice(&tables_blob);
+            hasher.update(&head);
+
+            let mut f = std::fs::File::create(output)?;
+            let mut w = std::io::BufWriter::new(&mut f);
+            w.write_all(&head)?;
             w.flush()?;
         }
 
         // spawn writer that appends entries
         let outfile = std::fs::OpenOptions::new().append(true).open(output)?;
         let writer_buf = std::io::BufWriter::new(outfile);
-        let out_clone2 = out_clone.clone();
+        let flush_every = opts.flush_every;
         let handle = thread::spawn(move || -> anyhow::Result<()> {
             let mut w = writer_buf;
+            let mut hasher = hasher;
             let mut written = 0usize;
             while let Ok(bytes) = bytes_rx.recv() {
-                let len = (bytes.len() as u32).to_le_bytes();
+                let len = vectro_lib::codec::record_len_prefix(bytes.len())?;
                 w.write_all(&len)?;
                 w.write_all(&bytes)?;
+                hasher.update(&len);
+                hasher.update(&bytes);
                 written += 1;
+                if flush_every.is_some_and(|n| n > 0 && written.is_multiple_of(n)) { w.flush()?; }
             }
+            // footer: zero-length sentinel, then CRC32 of everything before the CRC
+            let sentinel = 0u32.to_le_bytes();
+            w.write_all(&sentinel)?;
+            hasher.update(&sentinel);
+            w.write_all(&hasher.finalize().to_le_bytes())?;
             w.flush()?;
-            eprintln!("wrote {} entries to {}", written, out_clone2);
             Ok(())
         });
         writer_handle_opt = Some(handle);
@@ -170,8 +479,8 @@ pub fn compress_stream(input: &str, output: &str, quantize: bool) -> anyhow::Res
         }
 
         // feed collected embeddings into item_tx2
-        for emb in collected_embeddings {
-            let _ = item_tx2.send(emb);
+        for (id, vector) in ids.into_iter().zip(vectors) {
+            let _ = item_tx2.send(vectro_lib::Embedding::new(id, vector));
         }
         drop(item_tx2);
 
@@ -179,7 +488,8 @@ pub fn compress_stream(input: &str, output: &str, quantize: bool) -> anyhow::Res
         drop(bytes_tx);
         for h in worker_handles { let _ = h.join(); }
         // wait for writer
-        if let Some(h) = writer_handle_opt { let _ = h.join(); }
+        // surface write failures (e.g. a record too large for its length prefix)
+        if let Some(h) = writer_handle_opt { h.join().map_err(|_| anyhow::anyhow!("writer thread panicked"))??; }
 
     } else {
         // close item_tx to signal workers to finish
@@ -188,7 +498,8 @@ pub fn compress_stream(input: &str, output: &str, quantize: bool) -> anyhow::Res
         drop(bytes_tx);
         for h in worker_handles { let _ = h.join(); }
         // wait for writer
-        if let Some(h) = writer_handle_opt { let _ = h.join(); }
+        // surface write failures (e.g. a record too large for its length prefix)
+        if let Some(h) = writer_handle_opt { h.join().map_err(|_| anyhow::anyhow!("writer thread panicked"))??; }
     }
     if quantize {
     // If quantized, show a short summary including table count (attempt to read tables from file)
@@ -199,7 +510,7 @@ pub fn compress_stream(input: &str, output: &str, quantize: bool) -> anyhow::Res
             let mut hdr = vec![0u8; 16];
             let _ = f.read(&mut hdr);
             // crude: read table_count at offset header.len()
-            // header 'VECTRO+QSTREAM1\n' length is 14
+            // header 'VECTRO+QSTREAM3\n' length is 16
             if hdr.len() >= 16 {
                 // no-op; we will just display quantized
             }
@@ -208,7 +519,7 @@ pub fn compress_stream(input: &str, output: &str, quantize: bool) -> anyhow::Res
     } else {
         pb.finish_with_message(format!("wrote {} entries to {}", parsed, output));
     }
-    Ok(parsed)
+    Ok(CompressOutcome { written: parsed, rejected, cancelled })
 }
 
 #[cfg(test)]
@@ -216,6 +527,31 @@ mod tests {
     use super::*;
     use tempfile::NamedTempFile;
 
+    #[test]
+    fn progress_mode_follows_tty_and_quiet() {
+        assert_eq!(choose_progress_mode(true, false), ProgressMode::Spinner);
+        assert_eq!(choose_progress_mode(false, false), ProgressMode::Plain);
+        assert_eq!(choose_progress_mode(true, true), ProgressMode::Silent);
+        assert_eq!(choose_progress_mode(false, true), ProgressMode::Silent);
+        assert_eq!(Progress::new(ProgressMode::Plain).mode(), ProgressMode::Plain);
+    }
+
+    #[test]
+    fn progress_interval_targets_twenty_updates() {
+        assert_eq!(progress_interval(0), 1);
+        assert_eq!(progress_interval(7), 1);
+        assert_eq!(progress_interval(20), 1);
+        assert_eq!(progress_interval(21), 2);
+        assert_eq!(progress_interval(1_000), 50);
+        assert_eq!(progress_interval(10_000_000), 500_000);
+
+        // 100 lines of 49 bytes plus newline: an update every 5 records
+        let mut cadence = ProgressCadence::for_input(5_000, 49);
+        assert_eq!(cadence.every, 5);
+        let updates = (1..=100).filter(|&n| cadence.should_update(n)).count();
+        assert!(updates >= 20, "{}", updates);
+    }
+
     #[test]
     fn compress_small_file() {
         let tmp_in = NamedTempFile::new().unwrap();
@@ -233,6 +569,39 @@ mod tests {
         assert_eq!(ds.len(), 2);
     }
 
+    #[test]
+    fn streamed_search_matches_full_load() {
+        let tmp_in = NamedTempFile::new().unwrap();
+        let mut lines = String::new();
+        for i in 0..200 {
+            let x = (i as f32 * 0.37).sin();
+            let y = (i as f32 * 0.11).cos();
+            lines.push_str(&format!("{{\"id\":\"e{}\",\"vector\":[{},{},{}]}}\n", i, x, y, (i % 7) as f32));
+        }
+        // a duplicate vector so equal scores exercise the tie order
+        lines.push_str("{\"id\":\"dup\",\"vector\":[0.0,1.0,0.0]}\n");
+        std::fs::write(tmp_in.path(), lines).unwrap();
+        let tmp_out = NamedTempFile::new().unwrap();
+        let (inp, out) = (tmp_in.path().to_str().unwrap(), tmp_out.path().to_str().unwrap());
+        compress_stream(inp, out, false).unwrap();
+
+        let ds = vectro_lib::EmbeddingDataset::load(out).unwrap();
+        for query in [vec![1.0, 0.5, 0.0], vec![0.0, 1.0, 0.0], vec![-0.2, 0.1, 3.0]] {
+            for k in [0, 1, 10, 500] {
+                let expected: Vec<(String, f32)> = vectro_lib::search::top_k(&ds.embeddings, &query, k)
+                    .into_iter()
+                    .map(|(id, s)| (id.to_string(), s))
+                    .collect();
+                let streamed = vectro_lib::search::top_k_streamed(out, &query, k).unwrap();
+                assert_eq!(streamed, expected);
+            }
+        }
+
+        // quantized streams are not plain records
+        compress_stream(inp, out, true).unwrap();
+        assert!(vectro_lib::search::top_k_streamed(out, &[1.0, 0.0, 0.0], 3).is_err());
+    }
+
     #[test]
     fn compress_quantized() {
         let tmp_in = NamedTempFile::new().unwrap();
@@ -256,6 +625,157 @@ mod tests {
         assert!(ids.contains(&"three"));
     }
 
+    #[test]
+    fn compress_quantized_header_records_dim() {
+        let tmp_in = NamedTempFile::new().unwrap();
+        let in_path = tmp_in.path().to_str().unwrap().to_string();
+        std::fs::write(&in_path, r#"{"id":"one","vector":[1.0,2.0,3.0,4.0]}
+{"id":"two","vector":[4.0,5.0,6.0,7.0]}"#).unwrap();
+
+        let tmp_out = NamedTempFile::new().unwrap();
+        let out_path = tmp_out.path().to_str().unwrap().to_string();
+        compress_stream(&in_path, &out_path, true).expect("compress quantized");
+
+        // header layout: magic(16) flags(u8) table_count(u32) dim(u32)
+        let bytes = std::fs::read(&out_path).unwrap();
+        let hlen = vectro_lib::QSTREAM_HEADER_V3.len() + 1;
+        let dim = u32::from_le_bytes(bytes[hlen + 4..hlen + 8].try_into().unwrap());
+        assert_eq!(dim, 4);
+    }
+
+    #[test]
+    fn compress_normalize_quantize_tables_cover_unit_vectors() {
+        use vectro_lib::search::quant::QuantTable;
+        let tmp_in = NamedTempFile::new().unwrap();
+        let in_path = tmp_in.path().to_str().unwrap().to_string();
+        std::fs::write(&in_path, "a,300.0,400.0,0.0\nb,-50.0,0.0,120.0\nc,7.0,-24.0,0.0\nd,1.0,1.0,1.0\n").unwrap();
+        let tmp_out = NamedTempFile::new().unwrap();
+        let out_path = tmp_out.path().to_str().unwrap().to_string();
+
+        let opts = CompressOptions { quantize: true, normalize: true, ..Default::default() };
+        compress_with_options(&in_path, &out_path, &opts).expect("compress");
+
+        // tables follow magic(16) flags(u8) table_count(u32) dim(u32) tables_len(u32)
+        let bytes = std::fs::read(&out_path).unwrap();
+        let hlen = vectro_lib::QSTREAM_HEADER_V3.len() + 1;
+        let tables_len = u32::from_le_bytes(bytes[hlen + 8..hlen + 12].try_into().unwrap()) as usize;
+        let tables: Vec<QuantTable> = bincode::deserialize(&bytes[hlen + 12..hlen + 12 + tables_len]).unwrap();
+        // computed on the normalized vectors, not the raw magnitudes
+        assert!(tables.iter().all(|t| t.min >= -1.0 && t.max <= 1.0), "{:?}", tables);
+        assert!((tables[0].max - 0.6).abs() < 1e-6);
+
+        let ds = vectro_lib::EmbeddingDataset::load(&out_path).unwrap();
+        assert_eq!(ds.len(), 4);
+        for e in &ds.embeddings {
+            let n = e.vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+            assert!((n - 1.0).abs() < 0.01, "{} has norm {}", e.id, n);
+        }
+    }
+
+    #[test]
+    fn compress_quantized_detects_corruption() {
+        let tmp_in = NamedTempFile::new().unwrap();
+        let in_path = tmp_in.path().to_str().unwrap().to_string();
+        std::fs::write(&in_path, r#"{"id":"one","vector":[1.0,2.0,3.0]}
+{"id":"two","vector":[4.0,5.0,6.0]}"#).unwrap();
+
+        let tmp_out = NamedTempFile::new().unwrap();
+        let out_path = tmp_out.path().to_str().unwrap().to_string();
+        compress_stream(&in_path, &out_path, true).expect("compress quantized");
+        let bytes = std::fs::read(&out_path).unwrap();
+        assert!(bytes.starts_with(vectro_lib::QSTREAM_HEADER_V3));
+
+        // flip the last byte (part of the CRC footer)
+        let mut corrupt = bytes.clone();
+        *corrupt.last_mut().unwrap() ^= 0xff;
+        std::fs::write(&out_path, &corrupt).unwrap();
+        let err = vectro_lib::EmbeddingDataset::load(&out_path).unwrap_err();
+        assert!(err.to_string().contains("checksum"), "{}", err);
+
+        // dropping the footer is reported as truncation rather than a short load
+        std::fs::write(&out_path, &bytes[..bytes.len() - 8]).unwrap();
+        let err = vectro_lib::EmbeddingDataset::load(&out_path).unwrap_err();
+        assert!(err.to_string().contains("truncated"), "{}", err);
+
+        // nothing may follow the footer
+        let mut trailing = bytes.clone();
+        trailing.extend_from_slice(b"junk");
+        std::fs::write(&out_path, &trailing).unwrap();
+        let err = vectro_lib::EmbeddingDataset::load(&out_path).unwrap_err();
+        assert!(err.to_string().contains("trailing data"), "{}", err);
+    }
+
+    #[test]
+    fn compress_quantized_records_flags() {
+        use vectro_lib::search::quant::{StreamFlags, StreamMetric};
+        use vectro_lib::search::QuantizedIndex;
+        let tmp_in = NamedTempFile::new().unwrap();
+        let in_path = tmp_in.path().to_str().unwrap().to_string();
+        std::fs::write(&in_path, "a,3.0,4.0\nb,0.0,2.0\nc,-1.0,0.0\n").unwrap();
+        let tmp_out = NamedTempFile::new().unwrap();
+        let out_path = tmp_out.path().to_str().unwrap().to_string();
+
+        compress_stream(&in_path, &out_path, true).expect("compress raw");
+        let flags = vectro_lib::EmbeddingDataset::quantized_stream_flags(&out_path).unwrap();
+        assert_eq!(flags, Some(StreamFlags::default()));
+        let ds = vectro_lib::EmbeddingDataset::load(&out_path).unwrap();
+        assert!((ds.embeddings[0].vector[0] - 3.0).abs() < 0.05);
+
+        let opts = CompressOptions { quantize: true, normalize: true, metric: StreamMetric::Dot, ..Default::default() };
+        compress_with_options(&in_path, &out_path, &opts).expect("compress normalized");
+        let expected = StreamFlags { normalized: true, metric: StreamMetric::Dot };
+        assert_eq!(vectro_lib::EmbeddingDataset::quantized_stream_flags(&out_path).unwrap(), Some(expected));
+        let ds = vectro_lib::EmbeddingDataset::load(&out_path).unwrap();
+        for e in &ds.embeddings {
+            let n = e.vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+            assert!((n - 1.0).abs() < 0.02, "{} has norm {}", e.id, n);
+        }
+        let index = QuantizedIndex::load_stream(&out_path).unwrap();
+        assert_eq!(index.flags(), expected);
+        // dot product on the stored unit vectors: a = (0.6, 0.8) scores 0.6 + 0.8
+        let hits = index.top_k(&[1.0, 1.0], 1);
+        assert_eq!(hits[0].0, "a");
+        assert!((hits[0].1 - 1.4).abs() < 0.02, "{:?}", hits);
+    }
+
+    #[test]
+    fn compress_quantize_ragged_input_is_an_error() {
+        let tmp_in = NamedTempFile::new().unwrap();
+        let in_path = tmp_in.path().to_str().unwrap().to_string();
+        // the lenient parser drops "x" and keeps a two-component row
+        std::fs::write(&in_path, "a,1,2,x\nb,1,2,3\n").unwrap();
+        let tmp_out = NamedTempFile::new().unwrap();
+        let out_path = tmp_out.path().to_str().unwrap().to_string();
+
+        let err = compress_stream(&in_path, &out_path, true).unwrap_err().to_string();
+        assert!(err.contains("record \"b\" has 3 components, expected 2"), "{}", err);
+        // plain streams tolerate it
+        assert_eq!(compress_stream(&in_path, &out_path, false).unwrap(), 2);
+    }
+
+    #[test]
+    fn compress_plain_normalize_stores_unit_vectors() {
+        let tmp_in = NamedTempFile::new().unwrap();
+        let in_path = tmp_in.path().to_str().unwrap().to_string();
+        std::fs::write(&in_path, "a,3.0,4.0\nb,0.0,-2.0\nz,0.0,0.0\nc,1.0,1.0\n").unwrap();
+        let tmp_out = NamedTempFile::new().unwrap();
+        let out_path = tmp_out.path().to_str().unwrap().to_string();
+
+        let opts = CompressOptions { normalize: true, ..Default::default() };
+        assert_eq!(compress_with_options(&in_path, &out_path, &opts).unwrap(), 4);
+        let ds = vectro_lib::EmbeddingDataset::load(&out_path).unwrap();
+        assert_eq!(ds.embeddings[0].vector, vec![0.6, 0.8]);
+        for e in &ds.embeddings {
+            let n = e.vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+            if e.id == "z" {
+                // zero vectors are kept (with a warning) rather than dropped or NaN
+                assert_eq!(n, 0.0);
+            } else {
+                assert!((n - 1.0).abs() < 1e-6, "{} has norm {}", e.id, n);
+            }
+        }
+    }
+
     #[test]
     fn compress_csv_format() {
         let tmp_in = NamedTempFile::new().unwrap();
@@ -272,6 +792,156 @@ mod tests {
         assert_eq!(ds.len(), 2);
     }
 
+    #[test]
+    fn compress_strict_parse_rejects_bad_component() {
+        let tmp_in = NamedTempFile::new().unwrap();
+        let in_path = tmp_in.path().to_str().unwrap().to_string();
+        std::fs::write(&in_path, "id1,1.0,2.0,3.0\nid2,4.0,oops,6.0\nid3,7.0,8.0,9.0\n").unwrap();
+
+        let tmp_out = NamedTempFile::new().unwrap();
+        let out_path = tmp_out.path().to_str().unwrap().to_string();
+
+        // lenient: the bad component is dropped and the record kept with 2 components
+        let n = compress_stream(&in_path, &out_path, false).expect("compress lenient");
+        assert_eq!(n, 3);
+        let ds = vectro_lib::EmbeddingDataset::load(&out_path).expect("load");
+        let id2 = ds.embeddings.iter().find(|e| e.id == "id2").unwrap();
+        assert_eq!(id2.vector.len(), 2);
+
+        // strict: the whole record is rejected
+        let opts = CompressOptions { strict_parse: true, ..Default::default() };
+        let n = compress_with_options(&in_path, &out_path, &opts).expect("compress strict");
+        assert_eq!(n, 2);
+        let ds = vectro_lib::EmbeddingDataset::load(&out_path).expect("load");
+        assert!(ds.embeddings.iter().all(|e| e.id != "id2"));
+    }
+
+    #[test]
+    fn compress_strict_parse_rejects_dim_mismatch() {
+        let tmp_in = NamedTempFile::new().unwrap();
+        let in_path = tmp_in.path().to_str().unwrap().to_string();
+        std::fs::write(&in_path, r#"{"id":"a","vector":[1.0,2.0]}
+{"id":"b","vector":[1.0,2.0,3.0]}
+{"id":"c","vector":[3.0,4.0]}"#).unwrap();
+
+        let tmp_out = NamedTempFile::new().unwrap();
+        let out_path = tmp_out.path().to_str().unwrap().to_string();
+
+        let opts = CompressOptions { strict_parse: true, ..Default::default() };
+        let n = compress_with_options(&in_path, &out_path, &opts).expect("compress strict");
+        assert_eq!(n, 2);
+    }
+
+    #[test]
+    fn compress_with_limit() {
+        let tmp_in = NamedTempFile::new().unwrap();
+        let in_path = tmp_in.path().to_str().unwrap().to_string();
+        let data: String = (0..10).map(|i| format!("id{},{}.0,1.0\n", i, i)).collect();
+        std::fs::write(&in_path, data).unwrap();
+
+        let tmp_out = NamedTempFile::new().unwrap();
+        let out_path = tmp_out.path().to_str().unwrap().to_string();
+
+        for quantize in [false, true] {
+            let opts = CompressOptions { quantize, limit: Some(3), ..Default::default() };
+            let n = compress_with_options(&in_path, &out_path, &opts).expect("compress limited");
+            assert_eq!(n, 3);
+            let ds = vectro_lib::EmbeddingDataset::load(&out_path).expect("load");
+            assert_eq!(ds.len(), 3);
+        }
+    }
+
+    #[test]
+    fn compress_outcome_reports_rejected_lines() {
+        let tmp_in = NamedTempFile::new().unwrap();
+        let input = concat!(
+            "{\"id\":\"a\",\"vector\":[1.0,0.0]}\n",
+            "garbage\n",
+            "\n",
+            "b,0.0,1.0\n",
+            "{\"id\":\"c\",\"vector\":[1.0,\"x\"]}\n",
+            "c,1.0,2.0,3.0\n",
+        );
+        std::fs::write(tmp_in.path(), input).unwrap();
+        let tmp_out = NamedTempFile::new().unwrap();
+        let (inp, out) = (tmp_in.path().to_str().unwrap(), tmp_out.path().to_str().unwrap());
+
+        let opts = CompressOptions { strict_parse: true, ..Default::default() };
+        let outcome = compress_with_outcome(inp, out, &opts).unwrap();
+        assert_eq!(outcome.written, 2);
+        let lines: Vec<usize> = outcome.rejected.iter().map(|(n, _)| *n).collect();
+        assert_eq!(lines, vec![2, 5, 6]);
+        assert_eq!(outcome.rejected[0].1, "garbage");
+        assert_eq!(outcome.rejected[2].1, "c,1.0,2.0,3.0");
+
+        // the shim agrees on the count
+        assert_eq!(compress_with_options(inp, out, &opts).unwrap(), 2);
+        // lenient mode keeps the partially parsed rows; only the non-record line is rejected
+        let outcome = compress_with_outcome(inp, out, &CompressOptions::default()).unwrap();
+        assert_eq!(outcome.written, 4);
+        assert_eq!(outcome.rejected, vec![(2, "garbage".to_string())]);
+    }
+
+    // a FIFO lets the test cancel at an exact point: the reader blocks after the
+    // first records until more input arrives
+    #[cfg(unix)]
+    #[test]
+    fn compress_cancel_leaves_loadable_output() {
+        let dir = tempfile::tempdir().unwrap();
+        for quantize in [false, true] {
+            let fifo = dir.path().join(format!("input-{}.fifo", quantize));
+            assert!(std::process::Command::new("mkfifo").arg(&fifo).status().unwrap().success());
+            let out = dir.path().join(format!("out-{}.bin", quantize));
+
+            let cancel = CancelToken::new();
+            let feeder = {
+                let (fifo, cancel) = (fifo.clone(), cancel.clone());
+                std::thread::spawn(move || {
+                    let mut w = std::fs::OpenOptions::new().write(true).open(&fifo).unwrap();
+                    for i in 0..5 {
+                        writeln!(w, "{{\"id\":\"r{}\",\"vector\":[{}.0,1.0]}}", i, i).unwrap();
+                    }
+                    w.flush().unwrap();
+                    // let the reader consume those lines, then cancel before sending more
+                    std::thread::sleep(Duration::from_millis(500));
+                    cancel.cancel();
+                    for i in 5..1000 {
+                        if writeln!(w, "{{\"id\":\"r{}\",\"vector\":[{}.0,1.0]}}", i, i).is_err() { break; }
+                    }
+                })
+            };
+
+            let opts = CompressOptions { quantize, progress: Some(ProgressMode::Silent), ..Default::default() };
+            let outcome = compress_stream_cancellable(fifo.to_str().unwrap(), out.to_str().unwrap(), &opts, &cancel).unwrap();
+            feeder.join().unwrap();
+            assert!(outcome.cancelled);
+            assert_eq!(outcome.written, 5);
+
+            let ds = vectro_lib::EmbeddingDataset::load(out.to_str().unwrap()).unwrap();
+            let ids: Vec<&str> = ds.embeddings.iter().map(|e| e.id.as_str()).collect();
+            assert_eq!(ids.len(), 5);
+            assert!(ids.contains(&"r0") && ids.contains(&"r4"));
+        }
+    }
+
+    #[test]
+    fn compress_with_flush_every() {
+        let tmp_in = NamedTempFile::new().unwrap();
+        let lines: String = (0..25).map(|i| format!("{{\"id\":\"f{}\",\"vector\":[{}.0,1.0]}}\n", i, i)).collect();
+        std::fs::write(tmp_in.path(), lines).unwrap();
+        let tmp_out = NamedTempFile::new().unwrap();
+        let (inp, out) = (tmp_in.path().to_str().unwrap(), tmp_out.path().to_str().unwrap());
+
+        for quantize in [false, true] {
+            for flush_every in [Some(1), Some(7), Some(0)] {
+                let opts = CompressOptions { quantize, flush_every, ..Default::default() };
+                assert_eq!(compress_with_options(inp, out, &opts).unwrap(), 25);
+                let ds = vectro_lib::EmbeddingDataset::load(out).unwrap();
+                assert_eq!(ds.len(), 25);
+            }
+        }
+    }
+
     #[test]
     fn compress_with_empty_lines() {
         let tmp_in = NamedTempFile::new().unwrap();
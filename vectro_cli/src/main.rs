@@ -16,17 +16,24 @@
 //! // vectro serve --port 8080
 //! ```
 
-use clap::{Parser, Subcommand};
-use vectro_cli::compress_stream;
+use clap::{Parser, Subcommand, ValueEnum};
+use vectro_cli::{compress_with_outcome, detect_progress_mode, warn_skipped, CompressOptions, CompressOutcome, Progress};
+use vectro_lib::search::quant::StreamMetric;
 
 use serde_json::Value;
 
 pub mod server;
 
+/// One parsed Criterion summary row: (name, median, mean, unit)
+type BenchRow = (String, Option<f64>, Option<f64>, Option<String>);
+
 #[derive(Parser)]
-#[command(name = "vectro")]
+#[command(name = "vectro", version)]
 #[command(about = "Vectro+ — Rust embedding compressor & search tool", long_about = None)]
 struct Cli {
+    /// Suppress spinners and progress messages (warnings and results are still printed).
+    #[arg(long, global = true, default_value_t = false)]
+    quiet: bool,
     #[command(subcommand)]
     command: Commands,
 }
@@ -42,6 +49,23 @@ enum Commands {
         /// Use for large datasets where memory/storage is constrained.
         /// Default: false
         quantize: bool,
+        /// Reject a whole record if any component fails to parse or its length
+        /// differs from the first record (default: drop bad components and keep the row).
+        #[arg(long, default_value_t = false)]
+        strict_parse: bool,
+        /// Stop after this many successfully parsed records.
+        #[arg(long)]
+        limit: Option<usize>,
+        /// Flush the output every N records (more durable, slower); default: only at the end.
+        #[arg(long)]
+        flush_every: Option<usize>,
+        /// Store unit-length vectors (lossy: original magnitudes are dropped; zero vectors
+        /// are kept with a warning). Recorded in the header of quantized streams.
+        #[arg(long, default_value_t = false)]
+        normalize: bool,
+        /// Metric the quantized stream will be searched with (recorded in the stream header).
+        #[arg(long, value_enum, default_value_t = SimilarityMetric::Cosine, requires = "quantize")]
+        metric: SimilarityMetric,
     },
     /// Run library benchmarks (uses the `vectro_lib` bench harness).
     /// Streams benchmark output and shows a spinner while running.
@@ -69,24 +93,225 @@ enum Commands {
         /// Path to dataset (bincode). If omitted, uses built-in toy dataset.
         #[arg(long)]
         dataset: Option<String>,
+        /// Prebuilt index from `index`; skips building one from the dataset.
+        #[arg(long, conflicts_with = "dataset")]
+        index: Option<String>,
+    },
+    /// Build a SearchIndex from a dataset and save it for `search --index`.
+    Index {
+        /// Dataset to index (any format `compress` writes).
+        dataset: String,
+        /// Where to write the index.
+        output: String,
     },
     Serve {
         #[arg(short, long, default_value_t = 8080)]
         port: u16,
+        /// k used when a search request omits it
+        #[arg(long, default_value_t = server::default_top_k())]
+        default_k: usize,
+        /// Upper bound on k; larger requests are clamped
+        #[arg(long, default_value_t = server::default_max_k())]
+        max_k: usize,
+        /// Allowed CORS origin (repeatable); any origin when omitted
+        #[arg(long = "cors-origin")]
+        cors_origins: Vec<String>,
+        /// Require `Authorization: Bearer <token>` on /api and /ws routes; open the
+        /// dashboard as /?token=<token> to use it
+        #[arg(long)]
+        token: Option<String>,
+        /// Cache up to this many search responses for repeated queries (0 disables)
+        #[arg(long, default_value_t = 0)]
+        query_cache: usize,
+    },
+    /// Print size, dimension and data-quality problems of a dataset file.
+    Stats {
+        /// Path to a dataset written by `compress` (or `EmbeddingDataset::save`).
+        dataset: String,
+    },
+    /// Compare two literal vectors, e.g. `similarity 1,0 0.5,0.5`.
+    Similarity {
+        /// First vector, comma-separated.
+        #[arg(allow_hyphen_values = true)]
+        a: String,
+        /// Second vector, comma-separated; must have the same length as `a`.
+        #[arg(allow_hyphen_values = true)]
+        b: String,
+        #[arg(long, value_enum, default_value_t = SimilarityMetric::Cosine)]
+        metric: SimilarityMetric,
+    },
+    /// Measure how much quantization changes search results: recall@k and mean
+    /// score delta of a quantized index against the float index, using dataset
+    /// vectors as queries.
+    Recall {
+        /// Dataset to evaluate (any format `compress` writes).
+        dataset: String,
+        #[arg(short, long, default_value_t = 10)]
+        k: usize,
+        /// Use this many evenly spaced dataset vectors as queries; all when omitted.
+        #[arg(long)]
+        queries: Option<usize>,
+    },
+    /// Write a dataset as NDJSON (`{"id":..,"vector":[..]}` per line), the format
+    /// `compress` reads.
+    Export {
+        /// Dataset to export (any format `compress` writes).
+        dataset: String,
+        /// Output file.
+        output: String,
+        /// Round each component to this many significant digits (lossy: the rest of
+        /// the f32 precision is dropped); full precision when omitted.
+        #[arg(long)]
+        precision: Option<usize>,
     },
+    /// End-to-end smoke test: export the toy dataset, compress it (plain and
+    /// quantized), load it back, index it and search it. Exits non-zero on failure.
+    Selftest,
+    /// Print vectro_lib / vectro_cli versions and the dataset format version.
+    Version,
+}
+
+/// Scoring used by `similarity`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SimilarityMetric {
+    /// Cosine similarity in [-1, 1].
+    Cosine,
+    /// Euclidean (L2) distance; lower means more similar.
+    Euclidean,
+    /// Raw dot product.
+    Dot,
 }
 
 // Wrapper functions for testability
-fn execute_compress_command(input: &str, output: &str, quantize: bool) -> anyhow::Result<usize> {
-    crate::compress_stream(input, output, quantize)
+fn execute_compress_command(input: &str, output: &str, opts: &CompressOptions) -> anyhow::Result<CompressOutcome> {
+    compress_with_outcome(input, output, opts)
 }
 
-fn execute_serve_command(port: u16) -> anyhow::Result<()> {
+fn execute_serve_command(config: server::ServerConfig) -> anyhow::Result<()> {
     tokio::runtime::Runtime::new()?.block_on(async {
-        server::serve(port).await
+        let state = config.app_state();
+        server::serve_with_config(config, state).await
+    })
+}
+
+fn execute_stats_command(dataset: &str) -> anyhow::Result<String> {
+    let ds = vectro_lib::EmbeddingDataset::load(dataset)?;
+    let report = ds.validate();
+    let dim = report.dim.map_or("-".to_string(), |d| d.to_string());
+    let mut out = format!("embeddings: {}\ndimension: {}\n", report.total, dim);
+    for (label, ids) in [
+        ("dimension mismatch", &report.dim_mismatch),
+        ("zero norm", &report.zero_norm),
+        ("non-finite", &report.non_finite),
+        ("duplicate ids", &report.duplicate_ids),
+    ] {
+        out.push_str(&format!("{}: {}", label, ids.len()));
+        if !ids.is_empty() {
+            // long lists are truncated; the count above is always exact
+            let shown: Vec<&str> = ids.iter().take(10).map(String::as_str).collect();
+            let more = if ids.len() > shown.len() { ", ..." } else { "" };
+            out.push_str(&format!(" ({}{})", shown.join(", "), more));
+        }
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+fn execute_similarity_command(a: &str, b: &str, metric: SimilarityMetric) -> anyhow::Result<f32> {
+    use vectro_lib::search::Metric;
+
+    let parse = |s: &str| -> anyhow::Result<Vec<f32>> {
+        s.split(',')
+            .map(|c| c.trim().parse::<f32>().map_err(|_| anyhow::anyhow!("invalid component {:?} in {:?}", c.trim(), s)))
+            .collect()
+    };
+    let (a, b) = (parse(a)?, parse(b)?);
+    if a.len() != b.len() {
+        anyhow::bail!("vector lengths differ: {} vs {}", a.len(), b.len());
+    }
+    Ok(match metric {
+        SimilarityMetric::Cosine => {
+            if a.iter().all(|x| *x == 0.0) || b.iter().all(|x| *x == 0.0) {
+                anyhow::bail!("cosine is undefined for a zero vector");
+            }
+            vectro_lib::search::cosine(&a, &b)
+        }
+        SimilarityMetric::Euclidean => vectro_lib::search::euclidean(&a, &b),
+        SimilarityMetric::Dot => vectro_lib::search::Dot.score(&a, &b),
     })
 }
 
+/// Returns the number of embeddings written.
+fn execute_export_command(dataset: &str, output: &str, precision: Option<usize>) -> anyhow::Result<usize> {
+    let ds = vectro_lib::EmbeddingDataset::load(dataset)?;
+    let out = std::io::BufWriter::new(std::fs::File::create(output)?);
+    ds.to_ndjson(out, precision)?;
+    Ok(ds.len())
+}
+
+fn execute_recall_command(dataset: &str, k: usize, queries: Option<usize>) -> anyhow::Result<vectro_lib::search::RecallReport> {
+    use vectro_lib::search::{evaluate_recall, QuantizedIndex, SearchIndex};
+
+    let ds = vectro_lib::EmbeddingDataset::load(dataset)?;
+    let exact = SearchIndex::from_dataset(&ds.embeddings);
+    warn_skipped(&exact);
+    let quantized = QuantizedIndex::try_from_dataset(&ds.embeddings)?;
+    let step = queries.filter(|&n| n > 0).map_or(1, |n| ds.len().div_ceil(n).max(1));
+    let sample: Vec<Vec<f32>> = ds.embeddings.iter().step_by(step).map(|e| e.vector.clone()).collect();
+    let truth = exact.batch_top_k(&sample, k);
+    let approx = quantized.batch_top_k(&sample, k);
+    Ok(evaluate_recall(&truth, &approx, k))
+}
+
+/// Run the `selftest` pipeline in a scratch directory, returning one line per
+/// passed step; the first failing step is the error.
+fn execute_selftest_command(progress: vectro_cli::ProgressMode) -> anyhow::Result<String> {
+    use std::fmt::Write;
+    use vectro_lib::search::{QuantizedIndex, SearchIndex};
+
+    let dir = std::env::temp_dir().join(format!("vectro-selftest-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let result = (|| -> anyhow::Result<String> {
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let (input, plain, quantized) = (path("toy.jsonl"), path("toy.bin"), path("toy.qbin"));
+        let mut report = String::new();
+
+        let toy = vectro_lib::EmbeddingDataset::toy();
+        toy.to_ndjson(std::io::BufWriter::new(std::fs::File::create(&input)?), None)?;
+        writeln!(report, "export: {} embeddings", toy.len())?;
+
+        for (output, quantize) in [(&plain, false), (&quantized, true)] {
+            let opts = CompressOptions { quantize, progress: Some(progress), ..Default::default() };
+            let written = vectro_cli::compress_with_options(&input, output, &opts)?;
+            anyhow::ensure!(written == toy.len(), "compress (quantize={}) wrote {} of {} embeddings", quantize, written, toy.len());
+            let loaded = vectro_lib::EmbeddingDataset::load(output)?;
+            anyhow::ensure!(loaded.len() == toy.len(), "{} loaded {} of {} embeddings", output, loaded.len(), toy.len());
+        }
+        writeln!(report, "compress + load: plain and quantized")?;
+
+        let loaded = vectro_lib::EmbeddingDataset::load(&plain)?;
+        let query = [1.0, 0.1];
+        let float_top = SearchIndex::try_from_dataset(&loaded.embeddings)?.top_k(&query, 1).first().map(|(id, _)| id.to_string());
+        let quant_top = QuantizedIndex::load_stream(&quantized)?.top_k(&query, 1).first().map(|(id, _)| id.to_string());
+        for (name, top) in [("float", float_top), ("quantized", quant_top)] {
+            anyhow::ensure!(top.as_deref() == Some("one"), "{} search returned {:?}, expected \"one\"", name, top);
+        }
+        writeln!(report, "search: float and quantized top result is \"one\"")?;
+        Ok(report)
+    })();
+    let _ = std::fs::remove_dir_all(&dir);
+    result
+}
+
+fn version_info() -> String {
+    format!(
+        "vectro_lib {}\nvectro_cli {}\ndataset format {}",
+        vectro_lib::VERSION,
+        env!("CARGO_PKG_VERSION"),
+        vectro_lib::FORMAT_VERSION
+    )
+}
+
 fn parse_query_string(query: &str) -> Vec<f32> {
     query
         .split(',')
@@ -98,50 +323,84 @@ fn load_dataset_or_default(dataset_path: Option<&str>) -> Vec<vectro_lib::Embedd
     use std::path::Path;
     
     if let Some(path) = dataset_path {
-        if let Ok(ds) = vectro_lib::EmbeddingDataset::load(path) { return ds.embeddings }
+        if path.starts_with("http://") || path.starts_with("https://") {
+            #[cfg(feature = "http")]
+            match vectro_lib::EmbeddingDataset::load_url(path) {
+                Ok(ds) => return ds.embeddings,
+                Err(e) => eprintln!("failed to load {}: {}", path, e),
+            }
+            #[cfg(not(feature = "http"))]
+            eprintln!("loading {} needs a build with the `http` feature", path);
+        } else if let Ok(ds) = vectro_lib::EmbeddingDataset::load(path) {
+            return ds.embeddings;
+        }
     } else if Path::new("./dataset.bin").exists() {
         if let Ok(ds) = vectro_lib::EmbeddingDataset::load("./dataset.bin") {
             return ds.embeddings;
         }
     }
     
-    // Default toy dataset
-    vec![
-        vectro_lib::Embedding::new("one", vec![1.0, 0.0]),
-        vectro_lib::Embedding::new("two", vec![0.0, 1.0]),
-        vectro_lib::Embedding::new("three", vec![0.707, 0.707]),
-    ]
+    vectro_lib::EmbeddingDataset::toy().embeddings
 }
 
 fn execute_search_command(query: &str, top_k: usize, dataset: Option<&str>) -> Vec<(String, f32)> {
-    let vec = parse_query_string(query);
     let embeddings = load_dataset_or_default(dataset);
     let idx = vectro_lib::search::SearchIndex::from_dataset(&embeddings);
+    warn_skipped(&idx);
+    search_index(&idx, query, top_k)
+}
+
+fn execute_search_with_index(query: &str, top_k: usize, index: &str) -> anyhow::Result<Vec<(String, f32)>> {
+    let idx = vectro_lib::search::SearchIndex::load(index)?;
+    Ok(search_index(&idx, query, top_k))
+}
+
+fn search_index(idx: &vectro_lib::search::SearchIndex, query: &str, top_k: usize) -> Vec<(String, f32)> {
+    let vec = parse_query_string(query);
     idx.top_k(&vec, top_k)
         .into_iter()
         .map(|(id, score)| (id.to_string(), score))
         .collect()
 }
 
+/// Build and save an index; returns the number of indexed vectors.
+fn execute_index_command(dataset: &str, output: &str, pb: &Progress) -> anyhow::Result<usize> {
+    let ds = vectro_lib::EmbeddingDataset::load(dataset)?;
+    let idx = vectro_lib::search::SearchIndex::from_dataset_with_progress(&ds.embeddings, |done, total| {
+        pb.set_message(format!("indexed {}/{} vectors", done, total));
+    });
+    pb.finish_and_clear();
+    warn_skipped(&idx);
+    idx.save(output)?;
+    Ok(idx.len())
+}
+
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
+    let progress_mode = detect_progress_mode(cli.quiet);
 
     match cli.command {
-        Commands::Compress { input, output, quantize } => {
-            execute_compress_command(&input, &output, quantize)?;
+        Commands::Compress { input, output, quantize, strict_parse, limit, flush_every, normalize, metric } => {
+            let metric = match metric {
+                SimilarityMetric::Cosine => StreamMetric::Cosine,
+                SimilarityMetric::Euclidean => StreamMetric::Euclidean,
+                SimilarityMetric::Dot => StreamMetric::Dot,
+            };
+            let opts = CompressOptions { quantize, strict_parse, limit, progress: Some(progress_mode), flush_every, normalize, metric };
+            let outcome = execute_compress_command(&input, &output, &opts)?;
+            if let Some((first, _)) = outcome.rejected.first() {
+                eprintln!("skipped {} invalid lines (first at line {})", outcome.rejected.len(), first);
+            }
         }
         Commands::Bench { save_report, open_report, summary, report_dir: _, bench_args } => {
-            // Run cargo bench for vectro_lib and stream output. Show a spinner while running.
-            use indicatif::{ProgressBar, ProgressStyle};
+            // Run cargo bench for vectro_lib and stream output. Show progress while running.
             use std::process::Command;
             use std::io::{BufRead, BufReader};
             use std::thread;
             use std::fs;
             use std::path::PathBuf;
 
-            let pb = ProgressBar::new_spinner();
-            pb.set_style(ProgressStyle::with_template("{spinner} {msg}").unwrap());
-            pb.enable_steady_tick(std::time::Duration::from_millis(80));
+            let pb = Progress::new(progress_mode);
             pb.set_message("running benches...");
 
             let mut cmd = build_bench_command(bench_args.as_deref());
@@ -180,7 +439,7 @@ fn main() -> anyhow::Result<()> {
                     if summary {
                         // parse JSON summaries in target/criterion/*/new/*.json and present a clean table
                         if let Ok(entries) = fs::read_dir(&crit_dir) {
-                            let mut rows: Vec<(String, Option<f64>, Option<f64>, Option<String>)> = Vec::new();
+                            let mut rows: Vec<BenchRow> = Vec::new();
                             for e in entries.flatten() {
                                 let p = e.path();
                                 if p.is_dir() {
@@ -287,14 +546,45 @@ fn main() -> anyhow::Result<()> {
                 }
             }
         }
-        Commands::Search { query, top_k, dataset } => {
-            let results = execute_search_command(&query, top_k, dataset.as_deref());
+        Commands::Search { query, top_k, dataset, index } => {
+            let results = match index {
+                Some(index) => execute_search_with_index(&query, top_k, &index)?,
+                None => execute_search_command(&query, top_k, dataset.as_deref()),
+            };
             for (i, (id, score)) in results.into_iter().enumerate() {
                 println!("{}. {} -> {:.6}", i + 1, id, score);
             }
         }
-        Commands::Serve { port } => {
-            execute_serve_command(port)?;
+        Commands::Index { dataset, output } => {
+            let n = execute_index_command(&dataset, &output, &Progress::new(progress_mode))?;
+            eprintln!("indexed {} vectors into {}", n, output);
+        }
+        Commands::Serve { port, default_k, max_k, cors_origins, token, query_cache } => {
+            execute_serve_command(server::ServerConfig { port, cors_origins, token, max_k, default_k, query_cache })?;
+        }
+        Commands::Stats { dataset } => {
+            print!("{}", execute_stats_command(&dataset)?);
+        }
+        Commands::Similarity { a, b, metric } => {
+            println!("{:.6}", execute_similarity_command(&a, &b, metric)?);
+        }
+        Commands::Recall { dataset, k, queries } => {
+            let report = execute_recall_command(&dataset, k, queries)?;
+            println!("queries: {}", report.queries);
+            println!("recall@{}: {:.4}", k, report.recall);
+            println!("mean score delta: {:.6}", report.mean_score_delta);
+        }
+        Commands::Export { dataset, output, precision } => {
+            let n = execute_export_command(&dataset, &output, precision)?;
+            println!("wrote {} embeddings to {}", n, output);
+        }
+        Commands::Selftest => {
+            // compress summaries go to stderr (unless --quiet); keep the report readable
+            print!("{}", execute_selftest_command(progress_mode)?);
+            println!("selftest passed");
+        }
+        Commands::Version => {
+            println!("{}", version_info());
         }
     }
 
@@ -454,14 +744,10 @@ fn get_estimate(v: &Value, key: &str) -> Option<f64> {
     if let Some(direct) = find_number_in_json(v, key) { return Some(direct); }
     // try path: estimates -> key -> point_estimate
     if let Value::Object(map) = v {
-        if let Some(est) = map.get("estimates") {
-            if let Value::Object(est_map) = est {
-                if let Some(kv) = est_map.get(key) {
-                    if let Value::Object(kmap) = kv {
-                        if let Some(pe) = kmap.get("point_estimate") {
-                            return pe.as_f64();
-                        }
-                    }
+        if let Some(Value::Object(est_map)) = map.get("estimates") {
+            if let Some(Value::Object(kmap)) = est_map.get(key) {
+                if let Some(pe) = kmap.get("point_estimate") {
+                    return pe.as_f64();
                 }
             }
         }
@@ -485,7 +771,7 @@ fn get_bench_name(v: &Value) -> Option<String> {
 }
 
 /// Generate a compact HTML summary from benchmark results
-fn generate_html_summary(rows: &[(String, Option<f64>, Option<f64>, Option<String>)], history: &std::collections::HashMap<String, f64>) -> String {
+fn generate_html_summary(rows: &[BenchRow], history: &std::collections::HashMap<String, f64>) -> String {
     let mut html = String::from(r#"<!DOCTYPE html>
 <html>
 <head>
@@ -666,9 +952,9 @@ mod tests {
         let tmp_out = NamedTempFile::new().unwrap();
         let out_path = tmp_out.path().to_str().unwrap();
         
-        let result = execute_compress_command(in_path, out_path, false);
+        let result = execute_compress_command(in_path, out_path, &CompressOptions::default());
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 1);
+        assert_eq!(result.unwrap().written, 1);
     }
 
     #[test]
@@ -683,9 +969,10 @@ mod tests {
         let tmp_out = NamedTempFile::new().unwrap();
         let out_path = tmp_out.path().to_str().unwrap();
         
-        let result = execute_compress_command(in_path, out_path, true);
+        let opts = CompressOptions { quantize: true, ..Default::default() };
+        let result = execute_compress_command(in_path, out_path, &opts);
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 2);
+        assert_eq!(result.unwrap().written, 2);
     }
 
     #[test]
@@ -695,10 +982,121 @@ mod tests {
         let tmp_out = NamedTempFile::new().unwrap();
         let out_path = tmp_out.path().to_str().unwrap();
         
-        let result = execute_compress_command("/nonexistent/file.jsonl", out_path, false);
+        let result = execute_compress_command("/nonexistent/file.jsonl", out_path, &CompressOptions::default());
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_execute_stats_command() {
+        use tempfile::NamedTempFile;
+
+        let tmp = NamedTempFile::new().unwrap();
+        let path = tmp.path().to_str().unwrap();
+        let mut ds = vectro_lib::EmbeddingDataset::new();
+        ds.add(vectro_lib::Embedding::new("a", vec![1.0, 0.0]));
+        ds.add(vectro_lib::Embedding::new("b", vec![0.0, 0.0]));
+        ds.add(vectro_lib::Embedding::new("a", vec![0.5, 0.5]));
+        ds.save(path).unwrap();
+
+        let out = execute_stats_command(path).unwrap();
+        assert!(out.contains("embeddings: 3"));
+        assert!(out.contains("dimension: 2"));
+        assert!(out.contains("zero norm: 1 (b)"));
+        assert!(out.contains("duplicate ids: 1 (a)"));
+        assert!(out.contains("non-finite: 0\n"));
+
+        assert!(execute_stats_command("/nonexistent/dataset.bin").is_err());
+    }
+
+    #[test]
+    fn test_execute_recall_command() {
+        use tempfile::NamedTempFile;
+
+        let tmp = NamedTempFile::new().unwrap();
+        let path = tmp.path().to_str().unwrap();
+        // one vector per axis: every vector's nearest neighbor is itself by a wide margin
+        let mut ds = vectro_lib::EmbeddingDataset::new();
+        for i in 0..8 {
+            let mut v = vec![0.0; 8];
+            v[i] = 1.0 + i as f32;
+            ds.add(vectro_lib::Embedding::new(format!("axis{}", i), v));
+        }
+        ds.save(path).unwrap();
+
+        let report = execute_recall_command(path, 1, None).unwrap();
+        assert_eq!(report.queries, 8);
+        assert_eq!(report.recall, 1.0);
+        assert!(report.mean_score_delta < 0.01, "{:?}", report);
+        assert_eq!(execute_recall_command(path, 1, Some(4)).unwrap().queries, 4);
+
+        assert!(execute_recall_command("/nonexistent/dataset.bin", 1, None).is_err());
+    }
+
+    #[test]
+    fn test_execute_export_command_precision() {
+        use clap::Parser;
+        use tempfile::NamedTempFile;
+
+        let tmp = NamedTempFile::new().unwrap();
+        let path = tmp.path().to_str().unwrap();
+        let mut ds = vectro_lib::EmbeddingDataset::new();
+        ds.add(vectro_lib::Embedding::new("a", vec![0.12345679, -2.5433216, 1234.567]));
+        ds.add(vectro_lib::Embedding::new("b", vec![1.0 / 3.0, 0.0, -0.000123456]));
+        ds.save(path).unwrap();
+
+        let cli = Cli::try_parse_from(["vectro", "export", path, "out.jsonl", "--precision", "4"]).unwrap();
+        let Commands::Export { precision, .. } = cli.command else { panic!("Expected Export command") };
+        assert_eq!(precision, Some(4));
+
+        let out = NamedTempFile::new().unwrap();
+        let out_path = out.path().to_str().unwrap();
+        assert_eq!(execute_export_command(path, out_path, precision).unwrap(), 2);
+        let text = std::fs::read_to_string(out_path).unwrap();
+        assert_eq!(text.lines().next().unwrap(), r#"{"id":"a","vector":[0.1235,-2.543,1235.0]}"#);
+
+        // the export is valid compress input and reloads within the rounding error
+        let stream = NamedTempFile::new().unwrap();
+        let stream_path = stream.path().to_str().unwrap();
+        assert_eq!(vectro_cli::compress_stream(out_path, stream_path, false).unwrap(), 2);
+        let back = vectro_lib::EmbeddingDataset::load(stream_path).unwrap();
+        for (orig, got) in ds.embeddings.iter().zip(&back.embeddings) {
+            for (&x, &y) in orig.vector.iter().zip(&got.vector) {
+                assert!((x - y).abs() <= x.abs() * 5e-4, "{} vs {}", x, y);
+            }
+        }
+        assert!(execute_export_command("/nonexistent/dataset.bin", out_path, None).is_err());
+    }
+
+    #[test]
+    fn test_execute_similarity_command() {
+        let cos = execute_similarity_command("1,2,3", "1,2,3", SimilarityMetric::Cosine).unwrap();
+        assert!((cos - 1.0).abs() < 1e-6);
+        let cos = execute_similarity_command("1,0", "0,1", SimilarityMetric::Cosine).unwrap();
+        assert!(cos.abs() < 1e-6);
+
+        assert_eq!(execute_similarity_command("0,0", "3,4", SimilarityMetric::Euclidean).unwrap(), 5.0);
+        assert_eq!(execute_similarity_command("1,2", "3,4", SimilarityMetric::Dot).unwrap(), 11.0);
+
+        let err = execute_similarity_command("1,0,0", "1,0", SimilarityMetric::Cosine).unwrap_err();
+        assert!(err.to_string().contains("lengths differ: 3 vs 2"));
+        assert!(execute_similarity_command("1,x", "1,0", SimilarityMetric::Dot).is_err());
+        assert!(execute_similarity_command("0,0", "1,0", SimilarityMetric::Cosine).is_err());
+    }
+
+    #[test]
+    fn test_cli_parsing_similarity() {
+        use clap::Parser;
+
+        let cli = Cli::try_parse_from(["vectro", "similarity", "-1,0", "1,0", "--metric", "euclidean"]).unwrap();
+        match cli.command {
+            Commands::Similarity { a, b, metric } => {
+                assert_eq!((a.as_str(), b.as_str()), ("-1,0", "1,0"));
+                assert_eq!(metric, SimilarityMetric::Euclidean);
+            }
+            _ => panic!("Expected Similarity command"),
+        }
+    }
+
     #[test]
     fn test_parse_query_string() {
         let result = parse_query_string("1.0,2.0,3.0");
@@ -768,6 +1166,42 @@ mod tests {
         assert_eq!(results[0].0, "apple");
     }
 
+    #[test]
+    fn test_execute_index_command() {
+        use tempfile::NamedTempFile;
+
+        let tmp = NamedTempFile::new().unwrap();
+        let path = tmp.path().to_str().unwrap();
+        let mut ds = vectro_lib::EmbeddingDataset::new();
+        ds.add(vectro_lib::Embedding::new("apple", vec![1.0, 0.0, 0.0]));
+        ds.add(vectro_lib::Embedding::new("banana", vec![0.0, 1.0, 0.0]));
+        ds.save(path).unwrap();
+
+        let out = NamedTempFile::new().unwrap();
+        let out_path = out.path().to_str().unwrap();
+        let pb = Progress::new(vectro_cli::ProgressMode::Silent);
+        assert_eq!(execute_index_command(path, out_path, &pb).unwrap(), 2);
+        let results = execute_search_with_index("0.0,1.0,0.0", 1, out_path).unwrap();
+        assert_eq!(results[0].0, "banana");
+
+        assert!(execute_index_command("/nonexistent/dataset.bin", out_path, &pb).is_err());
+    }
+
+    #[test]
+    fn test_cli_parsing_search_index_conflicts_with_dataset() {
+        use clap::Parser;
+
+        let cli = Cli::try_parse_from(["vectro", "search", "1,0", "--index", "idx.bin"]).unwrap();
+        match cli.command {
+            Commands::Search { index, dataset, .. } => {
+                assert_eq!(index.as_deref(), Some("idx.bin"));
+                assert!(dataset.is_none());
+            }
+            _ => panic!("Expected Search command"),
+        }
+        assert!(Cli::try_parse_from(["vectro", "search", "1,0", "--index", "a", "--dataset", "b"]).is_err());
+    }
+
     #[test]
     fn test_cli_parsing_compress() {
         // Test that CLI can parse compress command
@@ -779,10 +1213,12 @@ mod tests {
         
         if let Ok(cli) = cli {
             match cli.command {
-                Commands::Compress { input, output, quantize } => {
+                Commands::Compress { input, output, quantize, strict_parse, limit, .. } => {
                     assert_eq!(input, "input.jsonl");
                     assert_eq!(output, "output.bin");
                     assert!(!quantize);
+                    assert!(!strict_parse);
+                    assert!(limit.is_none());
                 }
                 _ => panic!("Expected Compress command"),
             }
@@ -804,6 +1240,65 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_cli_parsing_compress_strict_parse() {
+        use clap::Parser;
+
+        let args = vec!["vectro", "compress", "in.jsonl", "out.bin", "--strict-parse"];
+        let cli = Cli::try_parse_from(args).unwrap();
+
+        match cli.command {
+            Commands::Compress { strict_parse, .. } => assert!(strict_parse),
+            _ => panic!("Expected Compress command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_parsing_compress_flush_every() {
+        use clap::Parser;
+
+        let cli = Cli::try_parse_from(["vectro", "compress", "in.jsonl", "out.bin", "--flush-every", "1"]).unwrap();
+        match cli.command {
+            Commands::Compress { flush_every, .. } => assert_eq!(flush_every, Some(1)),
+            _ => panic!("Expected Compress command"),
+        }
+        let cli = Cli::try_parse_from(["vectro", "compress", "in.jsonl", "out.bin"]).unwrap();
+        match cli.command {
+            Commands::Compress { flush_every, .. } => assert!(flush_every.is_none()),
+            _ => panic!("Expected Compress command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_parsing_compress_normalize_metric() {
+        use clap::Parser;
+
+        let args = ["vectro", "compress", "in.jsonl", "out.bin", "--quantize", "--normalize", "--metric", "dot"];
+        match Cli::try_parse_from(args).unwrap().command {
+            Commands::Compress { normalize, metric, .. } => {
+                assert!(normalize);
+                assert!(matches!(metric, SimilarityMetric::Dot));
+            }
+            _ => panic!("Expected Compress command"),
+        }
+        // --normalize applies to plain streams too; --metric only describes quantized output
+        assert!(Cli::try_parse_from(["vectro", "compress", "in.jsonl", "out.bin", "--normalize"]).is_ok());
+        assert!(Cli::try_parse_from(["vectro", "compress", "in.jsonl", "out.bin", "--metric", "dot"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_parsing_compress_limit() {
+        use clap::Parser;
+
+        let args = vec!["vectro", "compress", "in.jsonl", "out.bin", "--limit", "3"];
+        let cli = Cli::try_parse_from(args).unwrap();
+
+        match cli.command {
+            Commands::Compress { limit, .. } => assert_eq!(limit, Some(3)),
+            _ => panic!("Expected Compress command"),
+        }
+    }
+
     #[test]
     fn test_cli_parsing_search() {
         use clap::Parser;
@@ -812,7 +1307,7 @@ mod tests {
         let cli = Cli::try_parse_from(args).unwrap();
         
         match cli.command {
-            Commands::Search { query, top_k, dataset } => {
+            Commands::Search { query, top_k, dataset, .. } => {
                 assert_eq!(query, "1.0,0.0,0.0");
                 assert_eq!(top_k, 10); // default
                 assert!(dataset.is_none());
@@ -829,7 +1324,7 @@ mod tests {
         let cli = Cli::try_parse_from(args).unwrap();
         
         match cli.command {
-            Commands::Search { query, top_k, dataset } => {
+            Commands::Search { query, top_k, dataset, .. } => {
                 assert_eq!(query, "1.0,0.0");
                 assert_eq!(top_k, 5);
                 assert_eq!(dataset.as_deref(), Some("data.bin"));
@@ -846,8 +1341,13 @@ mod tests {
         let cli = Cli::try_parse_from(args).unwrap();
         
         match cli.command {
-            Commands::Serve { port } => {
+            Commands::Serve { port, default_k, max_k, cors_origins, token, query_cache } => {
                 assert_eq!(port, 8080); // default
+                assert_eq!(default_k, 10);
+                assert_eq!(max_k, 1000);
+                assert!(cors_origins.is_empty());
+                assert!(token.is_none());
+                assert_eq!(query_cache, 0);
             }
             _ => panic!("Expected Serve command"),
         }
@@ -861,19 +1361,110 @@ mod tests {
         let cli = Cli::try_parse_from(args).unwrap();
         
         match cli.command {
-            Commands::Serve { port } => {
+            Commands::Serve { port, .. } => {
                 assert_eq!(port, 3000);
             }
             _ => panic!("Expected Serve command"),
         }
     }
 
+    #[test]
+    fn test_cli_parsing_serve_auth_and_cors() {
+        use clap::Parser;
+
+        let cli = Cli::try_parse_from([
+            "vectro", "serve", "--token", "t", "--cors-origin", "https://a.example", "--cors-origin", "https://b.example",
+        ])
+        .unwrap();
+        match cli.command {
+            Commands::Serve { cors_origins, token, .. } => {
+                assert_eq!(cors_origins, ["https://a.example", "https://b.example"]);
+                assert_eq!(token.as_deref(), Some("t"));
+            }
+            _ => panic!("Expected Serve command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_parsing_serve_k_limits() {
+        use clap::Parser;
+
+        let cli = Cli::try_parse_from(["vectro", "serve", "--default-k", "5", "--max-k", "50"]).unwrap();
+        match cli.command {
+            Commands::Serve { default_k, max_k, .. } => {
+                assert_eq!(default_k, 5);
+                assert_eq!(max_k, 50);
+            }
+            _ => panic!("Expected Serve command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_parsing_serve_query_cache() {
+        use clap::Parser;
+
+        let cli = Cli::try_parse_from(["vectro", "serve", "--query-cache", "256"]).unwrap();
+        match cli.command {
+            Commands::Serve { query_cache, .. } => assert_eq!(query_cache, 256),
+            _ => panic!("Expected Serve command"),
+        }
+    }
+
+    #[test]
+    fn test_execute_selftest_command() {
+        use clap::Parser;
+
+        assert!(matches!(Cli::try_parse_from(["vectro", "selftest"]).unwrap().command, Commands::Selftest));
+        let report = execute_selftest_command(vectro_cli::ProgressMode::Silent).unwrap();
+        assert_eq!(report.lines().count(), 3, "{}", report);
+        assert!(report.contains("top result is \"one\""), "{}", report);
+    }
+
+    #[test]
+    fn test_load_dataset_or_default_is_toy() {
+        let embeddings = load_dataset_or_default(Some("/nonexistent/path.bin"));
+        assert_eq!(embeddings, vectro_lib::EmbeddingDataset::toy().embeddings);
+    }
+
+    #[test]
+    fn test_cli_version_flag() {
+        use clap::Parser;
+
+        // clap reports --version through a DisplayVersion "error"
+        let err = Cli::try_parse_from(["vectro", "--version"]).err().unwrap();
+        assert_eq!(err.kind(), clap::error::ErrorKind::DisplayVersion);
+        assert!(err.to_string().contains(env!("CARGO_PKG_VERSION")));
+    }
+
+    #[test]
+    fn test_cli_parsing_version_subcommand() {
+        use clap::Parser;
+
+        let cli = Cli::try_parse_from(["vectro", "version"]).unwrap();
+        assert!(matches!(cli.command, Commands::Version));
+
+        let info = version_info();
+        assert!(info.contains(&format!("vectro_lib {}", vectro_lib::VERSION)));
+        assert!(info.contains(&format!("dataset format {}", vectro_lib::FORMAT_VERSION)));
+    }
+
+    #[test]
+    fn test_cli_parsing_quiet_is_global() {
+        use clap::Parser;
+
+        let cli = Cli::try_parse_from(["vectro", "compress", "in.jsonl", "out.bin", "--quiet"]).unwrap();
+        assert!(cli.quiet);
+        let cli = Cli::try_parse_from(["vectro", "--quiet", "bench"]).unwrap();
+        assert!(cli.quiet);
+    }
+
     #[test]
     fn test_cli_parsing_bench() {
         use clap::Parser;
         
         let args = vec!["vectro", "bench"];
         let cli = Cli::try_parse_from(args).unwrap();
+        assert!(!cli.quiet);
         
         match cli.command {
             Commands::Bench { save_report, open_report, summary, .. } => {
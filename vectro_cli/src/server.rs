@@ -1,54 +1,287 @@
 use axum::{
-    extract::{Json, Query, State},
-    http::StatusCode,
-    response::Html,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Json, Path, Query, Request, State,
+    },
+    http::{header, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{Html, Response},
     routing::{get, post},
     Router,
 };
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
 use tokio::sync::RwLock;
 use tower_http::cors::{Any, CorsLayer};
-use vectro_lib::{Embedding, EmbeddingDataset, search::SearchIndex};
+use vectro_lib::{DatasetError, Embedding, EmbeddingDataset, search::SearchIndex};
 
 // Shared application state
 #[derive(Clone)]
 pub struct AppState {
-    index: Arc<RwLock<Option<SearchIndex>>>,
+    // the index is shared behind its own Arc so searches can score on the
+    // blocking pool without holding the lock
+    index: Arc<RwLock<Option<Arc<SearchIndex>>>>,
     embeddings: Arc<RwLock<Vec<Embedding>>>,
+    // named collections under `/api/collections/:name/*`, independent of the
+    // default index above
+    collections: Arc<RwLock<HashMap<String, Arc<SearchIndex>>>>,
+    // k used when a search omits it, and the hard upper bound on k
+    default_k: usize,
+    max_k: usize,
+    // searches whose requested k was cut down to `max_k`
+    clamped_queries: Arc<AtomicU64>,
+    // recent search responses; disabled (capacity 0) unless configured
+    query_cache: Arc<Mutex<QueryCache>>,
+}
+
+/// Collection (`None` for the default index), query bits and k of a cached search.
+type CacheKey = (Option<String>, Vec<u32>, usize);
+
+/// LRU cache of search responses for `ServerConfig::query_cache`.
+struct QueryCache {
+    capacity: usize,
+    // bumped on every clear, so a search that started before a rebuild is not cached
+    generation: u64,
+    tick: u64,
+    entries: HashMap<CacheKey, (u64, SearchResponse)>,
+    // last-use tick -> key, least recently used first
+    recency: BTreeMap<u64, CacheKey>,
+    hits: u64,
+    misses: u64,
+}
+
+impl QueryCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            generation: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    fn get(&mut self, key: &CacheKey) -> Option<SearchResponse> {
+        self.tick += 1;
+        let Some((last_used, response)) = self.entries.get_mut(key) else {
+            self.misses += 1;
+            return None;
+        };
+        self.hits += 1;
+        let key = self.recency.remove(last_used).expect("cached key has a recency entry");
+        *last_used = self.tick;
+        self.recency.insert(self.tick, key);
+        Some(response.clone())
+    }
+
+    /// Cache `response` unless the cache was cleared since `generation` was read.
+    fn insert(&mut self, key: CacheKey, response: SearchResponse, generation: u64) {
+        if generation != self.generation || self.capacity == 0 {
+            return;
+        }
+        self.tick += 1;
+        if let Some((last_used, _)) = self.entries.remove(&key) {
+            self.recency.remove(&last_used);
+        } else if self.entries.len() >= self.capacity {
+            if let Some((_, oldest)) = self.recency.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.recency.insert(self.tick, key.clone());
+        self.entries.insert(key, (self.tick, response));
+    }
+
+    fn clear(&mut self) {
+        self.generation += 1;
+        self.entries.clear();
+        self.recency.clear();
+    }
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AppState {
     pub fn new() -> Self {
+        Self::with_k_limits(default_top_k(), default_max_k())
+    }
+
+    /// State with a custom default `k` and upper bound on `k` for searches.
+    pub fn with_k_limits(default_k: usize, max_k: usize) -> Self {
         Self {
             index: Arc::new(RwLock::new(None)),
             embeddings: Arc::new(RwLock::new(Vec::new())),
+            collections: Arc::new(RwLock::new(HashMap::new())),
+            default_k,
+            max_k,
+            clamped_queries: Arc::new(AtomicU64::new(0)),
+            query_cache: Arc::new(Mutex::new(QueryCache::new(0))),
         }
     }
+
+    /// Cache up to `capacity` search responses (0 disables caching). Repeated
+    /// identical queries (same vector, k and collection) are then answered from
+    /// memory until the next upload or load replaces an index.
+    pub fn with_query_cache(mut self, capacity: usize) -> Self {
+        self.query_cache = Arc::new(Mutex::new(QueryCache::new(capacity)));
+        self
+    }
+
+    fn query_cache(&self) -> MutexGuard<'_, QueryCache> {
+        // the cache holds no invariants a panicked holder could break
+        self.query_cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Replace the served embeddings and rebuild the index, e.g. to start a server
+    /// with data already loaded.
+    pub async fn set_embeddings(&self, embeddings: Vec<Embedding>) {
+        let new_index = SearchIndex::from_dataset(&embeddings);
+        vectro_cli::warn_skipped(&new_index);
+        let mut current = self.embeddings.write().await;
+        *current = embeddings;
+        let mut index = self.index.write().await;
+        *index = Some(Arc::new(new_index));
+        self.query_cache().clear();
+    }
+
+    /// Create or replace the collection `name` with an index over `embeddings`.
+    pub async fn set_collection(&self, name: impl Into<String>, embeddings: &[Embedding]) {
+        let index = Arc::new(SearchIndex::from_dataset(embeddings));
+        vectro_cli::warn_skipped(&index);
+        self.collections.write().await.insert(name.into(), index);
+        self.query_cache().clear();
+    }
+
+    /// Index of the collection `name`, or a 404 for an unknown name.
+    async fn collection(&self, name: &str) -> Result<Arc<SearchIndex>, (StatusCode, String)> {
+        match self.collections.read().await.get(name) {
+            Some(idx) => Ok(Arc::clone(idx)),
+            None => Err((StatusCode::NOT_FOUND, format!("unknown collection '{}'", name))),
+        }
+    }
+
+    /// `k` a search will actually use: the request's value (or the default), clamped
+    /// to `max_k`. Clamps are counted for `/api/metrics`.
+    fn effective_k(&self, requested: Option<usize>) -> usize {
+        let k = requested.unwrap_or(self.default_k);
+        if k > self.max_k {
+            self.clamped_queries.fetch_add(1, Ordering::Relaxed);
+        }
+        k.min(self.max_k)
+    }
+}
+
+/// Settings for `build_router_with_config` / `serve_with_config`.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub port: u16,
+    /// Origins allowed by CORS (e.g. `https://example.com`); empty allows any origin.
+    pub cors_origins: Vec<String>,
+    /// When set, `/api/*` and `/ws/*` require `Authorization: Bearer <token>`;
+    /// the dashboard and `/health` stay open. The dashboard sends the token given
+    /// as `/?token=<token>`, or asks for it on the first 401.
+    pub token: Option<String>,
+    /// Upper bound on k; larger requests are clamped.
+    pub max_k: usize,
+    /// k used when a search request omits it.
+    pub default_k: usize,
+    /// Number of search responses to keep in an LRU cache; 0 disables it.
+    pub query_cache: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            port: 8080,
+            cors_origins: Vec::new(),
+            token: None,
+            max_k: default_max_k(),
+            default_k: default_top_k(),
+            query_cache: 0,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Empty state using this config's k limits and query cache size.
+    pub fn app_state(&self) -> AppState {
+        AppState::with_k_limits(self.default_k, self.max_k).with_query_cache(self.query_cache)
+    }
+
+    fn cors_layer(&self) -> anyhow::Result<CorsLayer> {
+        if self.cors_origins.is_empty() {
+            return Ok(build_cors_layer());
+        }
+        let origins = self
+            .cors_origins
+            .iter()
+            .map(|o| HeaderValue::from_str(o).map_err(|_| anyhow::anyhow!("invalid CORS origin {:?}", o)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(CorsLayer::new().allow_origin(origins).allow_methods(Any).allow_headers(Any))
+    }
 }
 
 // API request/response types
 #[derive(Debug, Deserialize)]
 pub struct SearchRequest {
     pub query: Vec<f32>,
-    #[serde(default = "default_top_k")]
-    pub k: usize,
+    /// Falls back to the server's default k when omitted
+    #[serde(default)]
+    pub k: Option<usize>,
 }
 
-fn default_top_k() -> usize {
+pub fn default_top_k() -> usize {
     10
 }
 
-#[derive(Debug, Serialize)]
+pub fn default_max_k() -> usize {
+    1000
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct SearchResult {
     pub id: String,
     pub score: f32,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct SearchResponse {
     pub results: Vec<SearchResult>,
     pub query_time_ms: f64,
+    /// k actually used after applying the default and the server's max
+    pub k: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BenchmarkRequest {
+    #[serde(default = "default_num_queries")]
+    pub num_queries: usize,
+    #[serde(default = "default_top_k")]
+    pub k: usize,
+}
+
+fn default_num_queries() -> usize {
+    100
+}
+
+/// Largest `num_queries` `/api/benchmark` accepts; each query is a random vector
+/// of the index dimension, so the cap bounds what one request can allocate.
+pub const MAX_BENCHMARK_QUERIES: usize = 10_000;
+
+#[derive(Debug, Serialize)]
+pub struct BenchmarkResponse {
+    pub num_queries: usize,
+    pub avg_latency_ms: f64,
+    pub qps: f64,
+    /// k actually used after applying the server's max
+    pub k: usize,
 }
 
 #[derive(Debug, Deserialize)]
@@ -63,6 +296,25 @@ pub struct StatsResponse {
     pub index_loaded: bool,
 }
 
+/// One entry of `GET /api/collections`.
+#[derive(Debug, Serialize)]
+pub struct CollectionStats {
+    pub name: String,
+    pub count: usize,
+    pub dimensions: Option<usize>,
+}
+
+/// `GET /api/metrics`: query cache counters since the server started.
+#[derive(Debug, Serialize)]
+pub struct MetricsResponse {
+    pub query_cache_capacity: usize,
+    pub query_cache_entries: usize,
+    pub query_cache_hits: u64,
+    pub query_cache_misses: u64,
+    /// searches and benchmarks whose requested k exceeded the server's max
+    pub clamped_queries: u64,
+}
+
 #[derive(Debug, Serialize)]
 pub struct HealthResponse {
     pub status: String,
@@ -90,37 +342,24 @@ async fn stats(State(state): State<AppState>) -> Json<StatsResponse> {
     })
 }
 
+async fn metrics(State(state): State<AppState>) -> Json<MetricsResponse> {
+    let cache = state.query_cache();
+    Json(MetricsResponse {
+        query_cache_capacity: cache.capacity,
+        query_cache_entries: cache.entries.len(),
+        query_cache_hits: cache.hits,
+        query_cache_misses: cache.misses,
+        clamped_queries: state.clamped_queries.load(Ordering::Relaxed),
+    })
+}
+
 async fn upload_embeddings(
     State(state): State<AppState>,
     Json(payload): Json<UploadRequest>,
 ) -> Result<Json<StatsResponse>, (StatusCode, String)> {
-    if payload.embeddings.is_empty() {
-        return Err((StatusCode::BAD_REQUEST, "No embeddings provided".to_string()));
-    }
-    
-    // Validate dimensions are consistent
-    let first_dim = payload.embeddings[0].vector.len();
-    for emb in &payload.embeddings {
-        if emb.vector.len() != first_dim {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                "Inconsistent embedding dimensions".to_string(),
-            ));
-        }
-    }
-    
-    // Update embeddings
-    let mut embeddings = state.embeddings.write().await;
-    *embeddings = payload.embeddings;
-    
-    // Rebuild index
-    let new_index = SearchIndex::from_dataset(&embeddings);
-    let mut index = state.index.write().await;
-    *index = Some(new_index);
-    
-    let count = embeddings.len();
-    drop(embeddings);
-    drop(index);
+    let first_dim = validate_upload(&payload.embeddings)?;
+    let count = payload.embeddings.len();
+    state.set_embeddings(payload.embeddings).await;
     
     Ok(Json(StatsResponse {
         count,
@@ -129,34 +368,206 @@ async fn upload_embeddings(
     }))
 }
 
+/// `POST /api/collections/:name/upload`: create or replace the named collection.
+async fn upload_collection(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(payload): Json<UploadRequest>,
+) -> Result<Json<StatsResponse>, (StatusCode, String)> {
+    let first_dim = validate_upload(&payload.embeddings)?;
+    state.set_collection(name, &payload.embeddings).await;
+
+    Ok(Json(StatsResponse {
+        count: payload.embeddings.len(),
+        dimensions: Some(first_dim),
+        index_loaded: true,
+    }))
+}
+
+/// Reject empty uploads and mixed dimensions; returns the common dimension.
+fn validate_upload(embeddings: &[Embedding]) -> Result<usize, (StatusCode, String)> {
+    let Some(first) = embeddings.first() else {
+        return Err((StatusCode::BAD_REQUEST, "No embeddings provided".to_string()));
+    };
+    let first_dim = first.vector.len();
+    if embeddings.iter().any(|emb| emb.vector.len() != first_dim) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Inconsistent embedding dimensions".to_string(),
+        ));
+    }
+    Ok(first_dim)
+}
+
+/// `GET /api/collections`: every named collection, sorted by name.
+async fn list_collections(State(state): State<AppState>) -> Json<Vec<CollectionStats>> {
+    let collections = state.collections.read().await;
+    let mut list: Vec<CollectionStats> = collections
+        .iter()
+        .map(|(name, idx)| CollectionStats {
+            name: name.clone(),
+            count: idx.len(),
+            dimensions: (!idx.is_empty()).then(|| idx.dim()),
+        })
+        .collect();
+    list.sort_by(|a, b| a.name.cmp(&b.name));
+    Json(list)
+}
+
 async fn search(
     State(state): State<AppState>,
     Json(payload): Json<SearchRequest>,
 ) -> Result<Json<SearchResponse>, (StatusCode, String)> {
-    let index = state.index.read().await;
-    
-    if index.is_none() {
-        return Err((StatusCode::NOT_FOUND, "No index loaded. Upload embeddings first.".to_string()));
+    run_search(&state, payload).await.map(Json)
+}
+
+/// `POST /api/collections/:name/search`: like `/api/search`, over one collection.
+async fn search_collection(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(payload): Json<SearchRequest>,
+) -> Result<Json<SearchResponse>, (StatusCode, String)> {
+    let idx = state.collection(&name).await?;
+    search_index(&state, Some(&name), idx, payload).await.map(Json)
+}
+
+/// Search shared by `POST /api/search` and `/ws/search`.
+async fn run_search(state: &AppState, payload: SearchRequest) -> Result<SearchResponse, (StatusCode, String)> {
+    let idx = match state.index.read().await.as_ref() {
+        Some(idx) => Arc::clone(idx),
+        None => return Err((StatusCode::NOT_FOUND, "No index loaded. Upload embeddings first.".to_string())),
+    };
+    search_index(state, None, idx, payload).await
+}
+
+/// Validate `payload` and run it against `idx` (the index of `collection`, `None`
+/// for the default one) on the blocking pool, going through the query cache.
+async fn search_index(
+    state: &AppState,
+    collection: Option<&str>,
+    idx: Arc<SearchIndex>,
+    payload: SearchRequest,
+) -> Result<SearchResponse, (StatusCode, String)> {
+    // an all-zero or non-finite query has no direction, so cosine scores are undefined
+    let norm_sq: f32 = payload.query.iter().map(|x| x * x).sum();
+    if norm_sq == 0.0 || !norm_sq.is_finite() {
+        return Err((StatusCode::BAD_REQUEST, "query vector has zero norm".to_string()));
     }
     
+    let k = state.effective_k(payload.k);
     let start = std::time::Instant::now();
+
+    let mut cached = None;
+    {
+        let mut cache = state.query_cache();
+        if cache.capacity > 0 {
+            let key: CacheKey = (collection.map(str::to_string), payload.query.iter().map(|x| x.to_bits()).collect(), k);
+            if let Some(mut hit) = cache.get(&key) {
+                hit.query_time_ms = start.elapsed().as_secs_f64() * 1000.0;
+                return Ok(hit);
+            }
+            cached = Some((key, cache.generation));
+        }
+    }
     
-    let idx = index.as_ref().unwrap();
-    let results = idx.top_k(&payload.query, payload.k);
+    // scoring is CPU-bound; run it on the blocking pool so async workers stay free
+    let search_results = tokio::task::spawn_blocking(move || {
+        idx.top_k(&payload.query, k)
+            .into_iter()
+            .map(|(id, score)| SearchResult {
+                id: id.to_string(),
+                score,
+            })
+            .collect::<Vec<SearchResult>>()
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("search task failed: {}", e)))?;
     
     let elapsed = start.elapsed().as_secs_f64() * 1000.0;
     
-    let search_results: Vec<SearchResult> = results
-        .into_iter()
-        .map(|(id, score)| SearchResult {
-            id: id.to_string(),
-            score,
-        })
-        .collect();
-    
-    Ok(Json(SearchResponse {
+    let response = SearchResponse {
         results: search_results,
         query_time_ms: elapsed,
+        k,
+    };
+    if let Some((key, generation)) = cached {
+        state.query_cache().insert(key, response.clone(), generation);
+    }
+    Ok(response)
+}
+
+/// Error frame sent on `/ws/search` instead of closing the socket.
+#[derive(Debug, Serialize)]
+pub struct WsError {
+    pub error: String,
+}
+
+async fn ws_search(ws: WebSocketUpgrade, State(state): State<AppState>) -> Response {
+    ws.on_upgrade(move |socket| handle_ws_search(socket, state))
+}
+
+/// Each text message is a `SearchRequest`; each reply is a `SearchResponse`, or a
+/// `WsError` for malformed messages and failed searches. Binary frames are ignored.
+async fn handle_ws_search(mut socket: WebSocket, state: AppState) {
+    while let Some(Ok(msg)) = socket.recv().await {
+        let text = match msg {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        let reply = match serde_json::from_str::<SearchRequest>(&text) {
+            Ok(payload) => match run_search(&state, payload).await {
+                Ok(response) => serde_json::to_string(&response),
+                Err((_, error)) => serde_json::to_string(&WsError { error }),
+            },
+            Err(e) => serde_json::to_string(&WsError { error: format!("invalid search message: {}", e) }),
+        };
+        let reply = reply.unwrap_or_else(|e| format!("{{\"error\":\"{}\"}}", e));
+        if socket.send(Message::Text(reply)).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn benchmark(
+    State(state): State<AppState>,
+    Json(payload): Json<BenchmarkRequest>,
+) -> Result<Json<BenchmarkResponse>, (StatusCode, String)> {
+    let idx = match state.index.read().await.as_ref() {
+        Some(idx) => Arc::clone(idx),
+        None => return Err((StatusCode::NOT_FOUND, "No index loaded. Upload embeddings first.".to_string())),
+    };
+    if payload.num_queries == 0 {
+        return Err((StatusCode::BAD_REQUEST, "num_queries must be positive".to_string()));
+    }
+    if payload.num_queries > MAX_BENCHMARK_QUERIES {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("num_queries must be at most {}", MAX_BENCHMARK_QUERIES),
+        ));
+    }
+
+    let k = state.effective_k(Some(payload.k));
+    let elapsed = tokio::task::spawn_blocking(move || {
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+        let queries: Vec<Vec<f32>> = (0..payload.num_queries)
+            .map(|_| (0..idx.dim()).map(|_| rng.gen_range(-1.0..1.0)).collect())
+            .collect();
+
+        let start = std::time::Instant::now();
+        let _ = idx.batch_top_k(&queries, k);
+        start.elapsed().as_secs_f64()
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("benchmark task failed: {}", e)))?;
+
+    let n = payload.num_queries as f64;
+    Ok(Json(BenchmarkResponse {
+        num_queries: payload.num_queries,
+        avg_latency_ms: elapsed * 1000.0 / n,
+        qps: if elapsed > 0.0 { n / elapsed } else { 0.0 },
+        k,
     }))
 }
 
@@ -170,20 +581,21 @@ async fn load_dataset_endpoint(
     ))?;
     
     let dataset = EmbeddingDataset::load(path).map_err(|e| {
-        (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to load dataset: {}", e))
+        let status = match &e {
+            DatasetError::Io(io) if io.kind() == std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            DatasetError::Io(_) | DatasetError::Serialize(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            DatasetError::Deserialize(_) | DatasetError::BadMagic | DatasetError::UnsupportedVersion(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+        };
+        (status, format!("Failed to load dataset: {}", e))
     })?;
     
     let embeddings_vec = dataset.embeddings;
     let count = embeddings_vec.len();
     let dimensions = embeddings_vec.first().map(|e| e.vector.len());
     
-    // Update state
-    let new_index = SearchIndex::from_dataset(&embeddings_vec);
-    let mut embeddings = state.embeddings.write().await;
-    *embeddings = embeddings_vec;
-    
-    let mut index = state.index.write().await;
-    *index = Some(new_index);
+    state.set_embeddings(embeddings_vec).await;
     
     Ok(Json(StatsResponse {
         count,
@@ -203,32 +615,94 @@ fn build_cors_layer() -> CorsLayer {
         .allow_headers(Any)
 }
 
-fn build_router(state: AppState) -> Router {
-    Router::new()
-        .route("/", get(index_page))
-        .route("/health", get(health))
+/// Router with permissive CORS and no authentication, using `state` as is.
+pub fn build_router(state: AppState) -> Router {
+    router(state, build_cors_layer(), None)
+}
+
+/// Router configured by `config`; its k limits and query cache replace those of
+/// `state`, which may already hold data (see `AppState::set_embeddings`).
+pub fn build_router_with_config(config: &ServerConfig, mut state: AppState) -> anyhow::Result<Router> {
+    state.default_k = config.default_k;
+    state.max_k = config.max_k;
+    state.query_cache = Arc::new(Mutex::new(QueryCache::new(config.query_cache)));
+    Ok(router(state, config.cors_layer()?, config.token.clone()))
+}
+
+fn router(state: AppState, cors: CorsLayer, token: Option<String>) -> Router {
+    let mut api = Router::new()
         .route("/api/stats", get(stats))
+        .route("/api/metrics", get(metrics))
         .route("/api/search", post(search))
+        .route("/ws/search", get(ws_search))
+        .route("/api/benchmark", post(benchmark))
         .route("/api/upload", post(upload_embeddings))
         .route("/api/load", get(load_dataset_endpoint))
-        .layer(build_cors_layer())
+        .route("/api/collections", get(list_collections))
+        .route("/api/collections/:name/search", post(search_collection))
+        .route("/api/collections/:name/upload", post(upload_collection));
+    if let Some(token) = token {
+        api = api.route_layer(middleware::from_fn_with_state(Arc::new(token), require_token));
+    }
+    Router::new()
+        .route("/", get(index_page))
+        .route("/health", get(health))
+        .merge(api)
+        .layer(cors)
         .with_state(state)
 }
 
+async fn require_token(State(token): State<Arc<String>>, req: Request, next: Next) -> Result<Response, StatusCode> {
+    let presented = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if presented.is_some_and(|p| tokens_match(p.as_bytes(), token.as_bytes())) {
+        Ok(next.run(req).await)
+    } else {
+        Err(StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// Compare a presented token with the secret in time that depends only on their
+/// lengths, so response timing does not reveal how long a matching prefix is.
+fn tokens_match(presented: &[u8], secret: &[u8]) -> bool {
+    presented.len() == secret.len() && presented.iter().zip(secret).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
 fn print_server_info(port: u16) {
     println!("🚀 Vectro+ server starting on http://localhost:{}", port);
     println!("📊 Dashboard: http://localhost:{}", port);
     println!("🔍 API endpoints:");
     println!("   GET  /health");
     println!("   GET  /api/stats");
+    println!("   GET  /api/metrics");
     println!("   POST /api/search");
+    println!("   GET  /ws/search (WebSocket)");
     println!("   POST /api/upload");
     println!("   GET  /api/load?path=<path>");
+    println!("   GET  /api/collections");
+    println!("   POST /api/collections/<name>/upload");
+    println!("   POST /api/collections/<name>/search");
 }
 
 pub async fn serve(port: u16) -> anyhow::Result<()> {
-    let state = AppState::new();
-    let app = build_router(state);
+    serve_with_state(port, AppState::new()).await
+}
+
+/// Serve a pre-configured state (e.g. custom k limits).
+pub async fn serve_with_state(port: u16, state: AppState) -> anyhow::Result<()> {
+    run(port, build_router(state)).await
+}
+
+/// Serve `state` on `config.port` with the config's CORS, token and k limits.
+pub async fn serve_with_config(config: ServerConfig, state: AppState) -> anyhow::Result<()> {
+    let app = build_router_with_config(&config, state)?;
+    run(config.port, app).await
+}
+
+async fn run(port: u16, app: Router) -> anyhow::Result<()> {
     let addr = format!("0.0.0.0:{}", port);
     
     print_server_info(port);
@@ -328,7 +802,7 @@ mod tests {
         let state = AppState::new();
         let payload = SearchRequest {
             query: vec![1.0, 0.0],
-            k: 10,
+            k: Some(10),
         };
         
         let result = search(State(state), Json(payload)).await;
@@ -351,7 +825,7 @@ mod tests {
         // Now search
         let search_payload = SearchRequest {
             query: vec![1.0, 0.0],
-            k: 1,
+            k: Some(1),
         };
         
         let result = search(State(state), Json(search_payload)).await;
@@ -362,6 +836,163 @@ mod tests {
         assert_eq!(response.0.results[0].id, "test1");
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_search_runs_off_async_worker() {
+        let state = AppState::new();
+        let upload_payload = UploadRequest {
+            embeddings: vec![
+                Embedding::new("x", vec![1.0, 0.0, 0.0]),
+                Embedding::new("y", vec![0.0, 1.0, 0.0]),
+                Embedding::new("z", vec![0.0, 0.0, 1.0]),
+            ],
+        };
+        let _ = upload_embeddings(State(state.clone()), Json(upload_payload)).await.unwrap();
+
+        // concurrent searches all go through spawn_blocking and return correct hits
+        let mut handles = Vec::new();
+        for (q, want) in [(vec![0.0, 1.0, 0.1], "y"), (vec![0.1, 0.0, 1.0], "z"), (vec![1.0, 0.1, 0.0], "x")] {
+            let st = state.clone();
+            handles.push(tokio::spawn(async move {
+                let res = search(State(st), Json(SearchRequest { query: q, k: Some(2) })).await.unwrap();
+                (res.0.results[0].id.clone(), res.0.results.len(), want)
+            }));
+        }
+        for h in handles {
+            let (got, len, want) = h.await.unwrap();
+            assert_eq!(got, want);
+            assert_eq!(len, 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_applies_default_k() {
+        let state = AppState::with_k_limits(3, 100);
+        let upload_payload = UploadRequest {
+            embeddings: (0..10).map(|i| Embedding::new(format!("d{}", i), vec![1.0, i as f32])).collect(),
+        };
+        let _ = upload_embeddings(State(state.clone()), Json(upload_payload)).await.unwrap();
+
+        let req: SearchRequest = serde_json::from_str(r#"{"query": [1.0, 0.0]}"#).unwrap();
+        let res = search(State(state), Json(req)).await.unwrap();
+        assert_eq!(res.0.k, 3);
+        assert_eq!(res.0.results.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_search_clamps_to_max_k() {
+        let state = AppState::with_k_limits(10, 4);
+        let upload_payload = UploadRequest {
+            embeddings: (0..10).map(|i| Embedding::new(format!("c{}", i), vec![1.0, i as f32])).collect(),
+        };
+        let _ = upload_embeddings(State(state.clone()), Json(upload_payload)).await.unwrap();
+
+        let req = SearchRequest { query: vec![1.0, 0.0], k: Some(1_000_000) };
+        let res = search(State(state.clone()), Json(req)).await.unwrap();
+        assert_eq!(res.0.k, 4);
+        assert_eq!(res.0.results.len(), 4);
+
+        // clamps are not silent: /api/metrics counts them, and in-range k is not counted
+        let req = SearchRequest { query: vec![1.0, 0.0], k: Some(4) };
+        let _ = search(State(state.clone()), Json(req)).await.unwrap();
+        assert_eq!(metrics(State(state)).await.0.clamped_queries, 1);
+    }
+
+    #[tokio::test]
+    async fn test_search_zero_query_rejected() {
+        let state = AppState::new();
+        let upload_payload = UploadRequest {
+            embeddings: vec![Embedding::new("a", vec![1.0, 0.0])],
+        };
+        let _ = upload_embeddings(State(state.clone()), Json(upload_payload)).await.unwrap();
+
+        let req = SearchRequest { query: vec![0.0, 0.0], k: Some(1) };
+        let err = search(State(state.clone()), Json(req)).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::BAD_REQUEST);
+        assert_eq!(err.1, "query vector has zero norm");
+
+        let req = SearchRequest { query: vec![f32::NAN, 1.0], k: Some(1) };
+        let err = search(State(state), Json(req)).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_ws_search_round_trip() {
+        use futures_util::{SinkExt, StreamExt};
+        use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+        let state = AppState::new();
+        let upload_payload = UploadRequest {
+            embeddings: vec![
+                Embedding::new("a", vec![1.0, 0.0]),
+                Embedding::new("b", vec![0.0, 1.0]),
+            ],
+        };
+        let _ = upload_embeddings(State(state.clone()), Json(upload_payload)).await.unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, build_router(state)).await.unwrap() });
+
+        type Client = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+        async fn next_json(ws: &mut Client, msg: &str) -> serde_json::Value {
+            ws.send(WsMessage::Text(msg.to_string())).await.unwrap();
+            match ws.next().await.unwrap().unwrap() {
+                WsMessage::Text(text) => serde_json::from_str(&text).unwrap(),
+                other => panic!("unexpected frame {:?}", other),
+            }
+        }
+
+        let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws/search", addr)).await.unwrap();
+
+        let reply = next_json(&mut ws, r#"{"query": [1.0, 0.1], "k": 1}"#).await;
+        assert_eq!(reply["k"], 1);
+        assert_eq!(reply["results"][0]["id"], "a");
+
+        // malformed messages get an error frame and the connection stays usable
+        let reply = next_json(&mut ws, "not json").await;
+        assert!(reply["error"].as_str().unwrap().contains("invalid search message"));
+        let reply = next_json(&mut ws, r#"{"query": [0.0, 0.0]}"#).await;
+        assert_eq!(reply["error"], "query vector has zero norm");
+        let reply = next_json(&mut ws, r#"{"query": [0.0, 1.0]}"#).await;
+        assert_eq!(reply["results"][0]["id"], "b");
+    }
+
+    #[tokio::test]
+    async fn test_benchmark_no_index() {
+        let state = AppState::new();
+        let payload = BenchmarkRequest { num_queries: 10, k: 5 };
+        let err = benchmark(State(state), Json(payload)).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_benchmark_with_index() {
+        let state = AppState::new();
+        let upload_payload = UploadRequest {
+            embeddings: (0..50)
+                .map(|i| Embedding::new(format!("e{}", i), vec![i as f32, 1.0, -(i as f32)]))
+                .collect(),
+        };
+        let _ = upload_embeddings(State(state.clone()), Json(upload_payload)).await.unwrap();
+
+        let payload = BenchmarkRequest { num_queries: 100, k: 10 };
+        let res = benchmark(State(state.clone()), Json(payload)).await.unwrap();
+        assert_eq!(res.0.num_queries, 100);
+        assert_eq!(res.0.k, 10);
+        assert!(res.0.qps > 0.0);
+        assert!(res.0.avg_latency_ms >= 0.0);
+
+        // --max-k applies to benchmarks like any other search
+        let capped = AppState::with_k_limits(10, 3);
+        capped.set_embeddings(vec![Embedding::new("a", vec![1.0, 0.0])]).await;
+        let payload = BenchmarkRequest { num_queries: 5, k: 500 };
+        assert_eq!(benchmark(State(capped), Json(payload)).await.unwrap().0.k, 3);
+
+        let payload = BenchmarkRequest { num_queries: MAX_BENCHMARK_QUERIES + 1, k: 10 };
+        let err = benchmark(State(state), Json(payload)).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::BAD_REQUEST);
+    }
+
     #[tokio::test]
     async fn test_search_wrong_dimension() {
         let state = AppState::new();
@@ -377,13 +1008,98 @@ mod tests {
         // Search with 3D query - doesn't error, just gives poor results
         let search_payload = SearchRequest {
             query: vec![1.0, 0.0, 0.0],
-            k: 1,
+            k: Some(1),
         };
         
         let result = search(State(state), Json(search_payload)).await;
         assert!(result.is_ok()); // No dimension validation in search
     }
 
+    #[tokio::test]
+    async fn test_collections_are_isolated() {
+        let state = AppState::new();
+        let upload = |embeddings| UploadRequest { embeddings };
+        let res = upload_collection(
+            State(state.clone()),
+            Path("docs".to_string()),
+            Json(upload(vec![Embedding::new("doc1", vec![1.0, 0.0]), Embedding::new("doc2", vec![0.0, 1.0])])),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.0.count, 2);
+        let images = upload(vec![Embedding::new("img1", vec![1.0, 0.0, 0.0])]);
+        let _ = upload_collection(State(state.clone()), Path("images".to_string()), Json(images)).await.unwrap();
+
+        let query = |q: Vec<f32>| Json(SearchRequest { query: q, k: Some(5) });
+        let docs = search_collection(State(state.clone()), Path("docs".to_string()), query(vec![1.0, 0.1])).await.unwrap();
+        let ids: Vec<&str> = docs.0.results.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, ["doc1", "doc2"]);
+        let images = search_collection(State(state.clone()), Path("images".to_string()), query(vec![1.0, 0.0, 0.0])).await.unwrap();
+        assert_eq!(images.0.results.len(), 1);
+        assert_eq!(images.0.results[0].id, "img1");
+
+        let err = search_collection(State(state.clone()), Path("audio".to_string()), query(vec![1.0])).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::NOT_FOUND);
+        // the default index is separate from the named collections
+        assert_eq!(search(State(state.clone()), query(vec![1.0, 0.0])).await.unwrap_err().0, StatusCode::NOT_FOUND);
+
+        // uploading again replaces the collection
+        let replacement = upload(vec![Embedding::new("doc3", vec![1.0, 1.0])]);
+        let _ = upload_collection(State(state.clone()), Path("docs".to_string()), Json(replacement)).await.unwrap();
+        let docs = search_collection(State(state.clone()), Path("docs".to_string()), query(vec![1.0, 0.1])).await.unwrap();
+        assert_eq!(docs.0.results.len(), 1);
+        assert_eq!(docs.0.results[0].id, "doc3");
+
+        let list = list_collections(State(state)).await;
+        let names: Vec<(&str, usize)> = list.0.iter().map(|c| (c.name.as_str(), c.count)).collect();
+        assert_eq!(names, [("docs", 1), ("images", 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_query_cache_serves_repeated_queries() {
+        let state = AppState::new().with_query_cache(2);
+        state.set_embeddings(vec![Embedding::new("a", vec![1.0, 0.0]), Embedding::new("b", vec![0.0, 1.0])]).await;
+        let query = |q: Vec<f32>, k| Json(SearchRequest { query: q, k: Some(k) });
+        let counters = |state: &AppState| {
+            let m = state.query_cache();
+            (m.hits, m.misses, m.entries.len())
+        };
+
+        let first = search(State(state.clone()), query(vec![1.0, 0.1], 2)).await.unwrap();
+        let second = search(State(state.clone()), query(vec![1.0, 0.1], 2)).await.unwrap();
+        assert_eq!(counters(&state), (1, 1, 1));
+        let ids = |r: &SearchResponse| r.results.iter().map(|r| r.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&second.0), ids(&first.0));
+        // k is part of the key
+        let _ = search(State(state.clone()), query(vec![1.0, 0.1], 1)).await.unwrap();
+        assert_eq!(counters(&state), (1, 2, 2));
+
+        // least recently used goes first: the k=2 entry was used last, so it survives
+        let _ = search(State(state.clone()), query(vec![1.0, 0.1], 2)).await.unwrap();
+        let _ = search(State(state.clone()), query(vec![0.0, 1.0], 2)).await.unwrap();
+        let _ = search(State(state.clone()), query(vec![1.0, 0.1], 2)).await.unwrap();
+        assert_eq!(counters(&state), (3, 3, 2));
+
+        // rebuilding the index invalidates everything
+        state.set_embeddings(vec![Embedding::new("c", vec![1.0, 0.0])]).await;
+        let fresh = search(State(state.clone()), query(vec![1.0, 0.1], 2)).await.unwrap();
+        assert_eq!(ids(&fresh.0), ["c"]);
+        let m = metrics(State(state)).await;
+        assert_eq!((m.0.query_cache_hits, m.0.query_cache_misses, m.0.query_cache_entries), (3, 4, 1));
+        assert_eq!(m.0.query_cache_capacity, 2);
+    }
+
+    #[tokio::test]
+    async fn test_query_cache_disabled_by_default() {
+        let state = AppState::new();
+        state.set_embeddings(vec![Embedding::new("a", vec![1.0, 0.0])]).await;
+        for _ in 0..2 {
+            let _ = search(State(state.clone()), Json(SearchRequest { query: vec![1.0, 0.0], k: None })).await.unwrap();
+        }
+        let m = metrics(State(state)).await;
+        assert_eq!((m.0.query_cache_hits, m.0.query_cache_misses, m.0.query_cache_entries), (0, 0, 0));
+    }
+
     #[test]
     fn test_default_top_k() {
         assert_eq!(default_top_k(), 10);
@@ -394,14 +1110,16 @@ mod tests {
         let json = r#"{"query": [1.0, 2.0], "k": 5}"#;
         let req: SearchRequest = serde_json::from_str(json).unwrap();
         assert_eq!(req.query, vec![1.0, 2.0]);
-        assert_eq!(req.k, 5);
+        assert_eq!(req.k, Some(5));
     }
 
     #[test]
     fn test_search_request_default_k_serde() {
         let json = r#"{"query": [1.0, 2.0]}"#;
         let req: SearchRequest = serde_json::from_str(json).unwrap();
-        assert_eq!(req.k, 10);
+        // omitted k resolves to the server default
+        assert_eq!(req.k, None);
+        assert_eq!(AppState::new().effective_k(req.k), 10);
     }
 
     #[test]
@@ -472,7 +1190,20 @@ mod tests {
         
         let result = load_dataset_endpoint(State(state), Query(params)).await;
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err().0, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(result.unwrap_err().0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_load_dataset_corrupt_file() {
+        use axum::extract::Query;
+
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(tmp.path(), b"VECTRO+QSTREAM2\n\x01").unwrap();
+        let mut params = std::collections::HashMap::new();
+        params.insert("path".to_string(), tmp.path().to_str().unwrap().to_string());
+
+        let result = load_dataset_endpoint(State(AppState::new()), Query(params)).await;
+        assert_eq!(result.unwrap_err().0, StatusCode::UNPROCESSABLE_ENTITY);
     }
 
     #[test]
@@ -491,6 +1222,70 @@ mod tests {
         // If we got here, router construction succeeded
     }
 
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match(b"s3cret", b"s3cret"));
+        assert!(!tokens_match(b"s3creT", b"s3cret"));
+        assert!(!tokens_match(b"s3cre", b"s3cret"));
+        assert!(!tokens_match(b"", b"s3cret"));
+    }
+
+    #[tokio::test]
+    async fn test_router_with_config() {
+        let config = ServerConfig {
+            token: Some("s3cret".to_string()),
+            cors_origins: vec!["https://example.com".to_string()],
+            max_k: 2,
+            ..Default::default()
+        };
+        let state = AppState::new();
+        state.set_embeddings(vec![
+            Embedding::new("a", vec![1.0, 0.0]),
+            Embedding::new("b", vec![0.0, 1.0]),
+            Embedding::new("c", vec![1.0, 1.0]),
+        ]).await;
+        let app = build_router_with_config(&config, state).unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let client = reqwest::Client::new();
+
+        let resp = client.get(format!("{}/health", base)).send().await.unwrap();
+        assert_eq!(resp.status(), 200);
+        // the open dashboard must be able to authenticate its own /api calls
+        let page = client.get(format!("{}/?token=s3cret", base)).send().await.unwrap().text().await.unwrap();
+        assert!(page.contains("headers['Authorization'] = `Bearer ${token}`"));
+        assert!(!page.contains("await fetch('/api"));
+        let resp = client.get(format!("{}/api/stats", base)).send().await.unwrap();
+        assert_eq!(resp.status(), 401);
+        let resp = client.get(format!("{}/api/stats", base)).bearer_auth("wrong").send().await.unwrap();
+        assert_eq!(resp.status(), 401);
+
+        let resp = client.get(format!("{}/api/stats", base)).bearer_auth("s3cret").send().await.unwrap();
+        assert_eq!(resp.status(), 200);
+        let stats: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(stats["count"], 3);
+        assert_eq!(stats["index_loaded"], true);
+
+        // the config's max_k replaced the state's
+        let search: serde_json::Value = client
+            .post(format!("{}/api/search", base))
+            .bearer_auth("s3cret")
+            .json(&serde_json::json!({"query": [1.0, 0.0], "k": 10}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(search["k"], 2);
+        assert_eq!(search["results"][0]["id"], "a");
+
+        let bad = ServerConfig { cors_origins: vec!["bad\norigin".to_string()], ..Default::default() };
+        assert!(build_router_with_config(&bad, AppState::new()).is_err());
+    }
+
     #[test]
     fn test_print_server_info() {
         // Test that print_server_info doesn't panic
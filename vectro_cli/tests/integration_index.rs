@@ -0,0 +1,44 @@
+/// Integration tests for `index` + `search --index` through the CLI binary
+use std::process::Command;
+use tempfile::NamedTempFile;
+
+fn run(args: &[&str]) -> String {
+    let out = Command::new(env!("CARGO_BIN_EXE_vectro_cli"))
+        .args(args)
+        .output()
+        .expect("failed to run vectro_cli");
+    assert!(out.status.success(), "vectro_cli {:?} failed: {}", args, String::from_utf8_lossy(&out.stderr));
+    String::from_utf8(out.stdout).unwrap()
+}
+
+#[test]
+fn test_search_via_prebuilt_index_matches_dataset() {
+    let dataset = NamedTempFile::new().unwrap();
+    let dataset_path = dataset.path().to_str().unwrap();
+    let mut ds = vectro_lib::EmbeddingDataset::new();
+    for i in 0..50 {
+        let x = i as f32 * 0.13;
+        ds.add(vectro_lib::Embedding::new(format!("doc{}", i), vec![x.sin(), x.cos(), (i % 5) as f32]));
+    }
+    ds.save(dataset_path).unwrap();
+
+    let index = NamedTempFile::new().unwrap();
+    let index_path = index.path().to_str().unwrap();
+    run(&["index", dataset_path, index_path]);
+
+    for query in ["1.0,0.0,0.0", "0.2,0.9,3.0"] {
+        let from_dataset = run(&["search", query, "--top-k", "5", "--dataset", dataset_path]);
+        let from_index = run(&["search", query, "--top-k", "5", "--index", index_path]);
+        assert_eq!(from_index, from_dataset);
+        assert_eq!(from_index.lines().count(), 5);
+    }
+}
+
+#[test]
+fn test_search_with_missing_index_fails() {
+    let out = Command::new(env!("CARGO_BIN_EXE_vectro_cli"))
+        .args(["search", "1.0,0.0", "--index", "/nonexistent/index.bin"])
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+}
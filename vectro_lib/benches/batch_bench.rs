@@ -0,0 +1,33 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use vectro_lib::{Embedding, search::SearchIndex};
+
+fn make_dataset(n: usize, dim: usize) -> Vec<Embedding> {
+    (0..n)
+        .map(|i| {
+            let v: Vec<f32> = (0..dim).map(|d| (((i * 31 + d * 17) % 1000) as f32 / 500.0) - 1.0).collect();
+            Embedding::new(format!("id_{}", i), v)
+        })
+        .collect()
+}
+
+// a few expensive queries: the case where rayon's default split is a poor fit
+fn bench_few_queries(c: &mut Criterion) {
+    let ds = make_dataset(100_000, 128);
+    let idx = SearchIndex::from_dataset(&ds);
+    let queries: Vec<Vec<f32>> = ds.iter().step_by(25_000).map(|e| e.vector.clone()).collect();
+
+    let mut group = c.benchmark_group("batch_topk_4q_100k_x_128");
+    group.sample_size(20);
+    group.bench_function("default", |b| b.iter(|| {
+        let _ = idx.batch_top_k(&queries, 10);
+    }));
+    for chunk in [1, 4] {
+        group.bench_function(format!("chunk_{}", chunk), |b| b.iter(|| {
+            let _ = idx.batch_top_k_with_chunk(&queries, 10, chunk);
+        }));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_few_queries);
+criterion_main!(benches);
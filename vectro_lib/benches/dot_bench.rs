@@ -0,0 +1,29 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use vectro_lib::search::{cosine_batch, dot_fma, dot_naive};
+
+fn make_vectors(n: usize, dim: usize) -> Vec<Vec<f32>> {
+    (0..n)
+        .map(|i| (0..dim).map(|d| (((i * 31 + d * 17) % 1000) as f32 / 500.0) - 1.0).collect())
+        .collect()
+}
+
+// high-dimensional rows (OpenAI-sized embeddings); build with
+// RUSTFLAGS="-C target-cpu=native" on x86_64 so `dot` picks the FMA path
+fn bench_dot(c: &mut Criterion) {
+    let rows = make_vectors(20_000, 1536);
+    let query = rows[0].clone();
+
+    let mut group = c.benchmark_group("dot_20k_x_1536");
+    group.sample_size(20);
+    group.bench_function("naive", |b| b.iter(|| {
+        rows.iter().map(|r| dot_naive(&query, r)).sum::<f32>()
+    }));
+    group.bench_function("fma", |b| b.iter(|| {
+        rows.iter().map(|r| dot_fma(&query, r)).sum::<f32>()
+    }));
+    group.bench_function("cosine_batch", |b| b.iter(|| cosine_batch(&query, &rows)));
+    group.finish();
+}
+
+criterion_group!(benches, bench_dot);
+criterion_main!(benches);
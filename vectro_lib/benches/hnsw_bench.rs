@@ -0,0 +1,44 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use vectro_lib::{Embedding, search::{HnswIndex, SearchIndex}};
+
+// clustered dataset: points scattered around a handful of random-ish centers
+fn make_clustered(n: usize, dim: usize, clusters: usize) -> Vec<Embedding> {
+    let centers: Vec<Vec<f32>> = (0..clusters)
+        .map(|c| (0..dim).map(|d| (((c * 7919 + d * 104729) % 1000) as f32 / 500.0) - 1.0).collect())
+        .collect();
+    (0..n)
+        .map(|i| {
+            let center = &centers[i % clusters];
+            let v: Vec<f32> = center
+                .iter()
+                .enumerate()
+                .map(|(d, x)| x + (((i * 31 + d * 17) % 100) as f32 - 50.0) * 0.01)
+                .collect();
+            Embedding::new(format!("id_{}", i), v)
+        })
+        .collect()
+}
+
+fn bench_hnsw(c: &mut Criterion) {
+    let ds = make_clustered(20_000, 64, 32);
+    let query = ds[3].vector.clone();
+
+    let exact = SearchIndex::from_dataset(&ds);
+    let hnsw = HnswIndex::build(&ds, 16, 100, 42);
+    let truth: std::collections::HashSet<&str> = exact.top_k(&query, 10).into_iter().map(|(id, _)| id).collect();
+    let hits = hnsw.top_k(&query, 10, 64).iter().filter(|(id, _)| truth.contains(id)).count();
+    println!("hnsw recall@10 for the bench query: {}/10", hits);
+
+    let mut group = c.benchmark_group("hnsw_topk_20k_x_64");
+    group.sample_size(20);
+    group.bench_function("brute_force", |b| b.iter(|| {
+        let _ = exact.top_k(&query, 10);
+    }));
+    group.bench_function("hnsw_ef64", |b| b.iter(|| {
+        let _ = hnsw.top_k(&query, 10, 64);
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, bench_hnsw);
+criterion_main!(benches);
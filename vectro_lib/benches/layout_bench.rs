@@ -0,0 +1,33 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use vectro_lib::{Embedding, search::{SearchIndex, StorageLayout}};
+
+// synthetic dataset generator
+fn make_dataset(n: usize, dim: usize) -> Vec<Embedding> {
+    let mut out = Vec::with_capacity(n);
+    for i in 0..n {
+        let v: Vec<f32> = (0..dim).map(|d| ((i * 31 + d * 7) % 101) as f32 / 101.0).collect();
+        out.push(Embedding::new(format!("id_{}", i), v));
+    }
+    out
+}
+
+fn bench_layouts(c: &mut Criterion) {
+    let ds = make_dataset(100_000, 256);
+    let query = ds[0].vector.clone();
+
+    let nested = SearchIndex::from_dataset_with_layout(&ds, StorageLayout::Nested);
+    let contiguous = SearchIndex::from_dataset_with_layout(&ds, StorageLayout::Contiguous);
+
+    let mut group = c.benchmark_group("layout_topk_100k_x_256");
+    group.sample_size(10);
+    group.bench_function("nested", |b| b.iter(|| {
+        let _ = nested.top_k(&query, 10);
+    }));
+    group.bench_function("contiguous", |b| b.iter(|| {
+        let _ = contiguous.top_k(&query, 10);
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, bench_layouts);
+criterion_main!(benches);
@@ -0,0 +1,43 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use vectro_lib::{Embedding, search::SearchIndex};
+
+// clustered dataset: points scattered around a handful of random-ish centers
+fn make_clustered(n: usize, dim: usize, clusters: usize) -> Vec<Embedding> {
+    let centers: Vec<Vec<f32>> = (0..clusters)
+        .map(|c| (0..dim).map(|d| (((c * 7919 + d * 104729) % 1000) as f32 / 500.0) - 1.0).collect())
+        .collect();
+    (0..n)
+        .map(|i| {
+            let center = &centers[i % clusters];
+            let v: Vec<f32> = center
+                .iter()
+                .enumerate()
+                .map(|(d, x)| x + (((i * 31 + d * 17) % 100) as f32 - 50.0) * 0.001)
+                .collect();
+            Embedding::new(format!("id_{}", i), v)
+        })
+        .collect()
+}
+
+fn bench_pruned(c: &mut Criterion) {
+    let ds = make_clustered(50_000, 64, 32);
+    let query = ds[3].vector.clone();
+
+    let mut idx = SearchIndex::from_dataset(&ds);
+    idx.precompute_pruning();
+    let (_, scored) = idx.top_k_pruned_with_count(&query, 10);
+    println!("top_k_pruned fully scored {} of {} vectors", scored, ds.len());
+
+    let mut group = c.benchmark_group("pruned_topk_50k_x_64");
+    group.sample_size(20);
+    group.bench_function("full_scan", |b| b.iter(|| {
+        let _ = idx.top_k(&query, 10);
+    }));
+    group.bench_function("pruned", |b| b.iter(|| {
+        let _ = idx.top_k_pruned(&query, 10);
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, bench_pruned);
+criterion_main!(benches);
@@ -1,5 +1,5 @@
-use criterion::{criterion_group, criterion_main, Criterion};
-use vectro_lib::{Embedding, search::{SearchIndex, QuantizedIndex}};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use vectro_lib::{Embedding, search::{quant, SearchIndex, QuantizedIndex}};
 
 // synthetic dataset generator
 fn make_dataset(n: usize, dim: usize) -> Vec<Embedding> {
@@ -36,5 +36,53 @@ fn bench_search(c: &mut Criterion) {
     }));
 }
 
-criterion_group!(benches, bench_search);
+// the serving pattern: many queries against one precomputed index
+fn bench_repeated_precomputed(c: &mut Criterion) {
+    let ds = make_dataset(50_000, 64);
+    let queries: Vec<Vec<f32>> = ds.iter().step_by(5_000).map(|e| e.vector.clone()).collect();
+    let mut qidx = QuantizedIndex::from_dataset(&ds);
+    qidx.precompute_normalized();
+
+    let mut group = c.benchmark_group("quant_topk_precomputed_50k_x_64");
+    group.sample_size(30);
+    group.bench_function("10_queries", |b| b.iter(|| {
+        for q in &queries {
+            let _ = qidx.top_k(q, 10);
+        }
+    }));
+    group.finish();
+}
+
+fn bench_quantize(c: &mut Criterion) {
+    let ds = make_dataset(200_000, 128);
+    let vectors: Vec<Vec<f32>> = ds.into_iter().map(|e| e.vector).collect();
+
+    let mut group = c.benchmark_group("quantize_dataset_200k_x_128");
+    group.sample_size(10);
+    group.bench_function("serial", |b| b.iter(|| {
+        let _ = quant::quantize_dataset_serial(&vectors);
+    }));
+    group.bench_function("parallel", |b| b.iter(|| {
+        let _ = quant::try_quantize_dataset(&vectors).unwrap();
+    }));
+    group.finish();
+}
+
+// index construction, the bulk of the server's upload path
+fn bench_build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bench_build");
+    group.sample_size(10);
+    for n in [10_000, 100_000] {
+        let ds = make_dataset(n, 64);
+        group.bench_with_input(BenchmarkId::new("float_from_dataset", n), &ds, |b, ds| b.iter(|| {
+            let _ = SearchIndex::from_dataset(ds);
+        }));
+        group.bench_with_input(BenchmarkId::new("quant_from_dataset", n), &ds, |b, ds| b.iter(|| {
+            let _ = QuantizedIndex::from_dataset(ds);
+        }));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_search, bench_repeated_precomputed, bench_quantize, bench_build);
 criterion_main!(benches);
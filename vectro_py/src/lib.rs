@@ -1,9 +1,13 @@
+// pyo3 0.20 macros expand `impl` blocks inside generated functions, which newer
+// rustc flags as non-local; the lint is cosmetic for extension modules.
+#![allow(non_local_definitions)]
+
 use pyo3::prelude::*;
 use pyo3::types::{PyList, PyTuple};
 use numpy::{IntoPyArray, PyArray1, PyArray2, PyReadonlyArray1, PyReadonlyArray2};
-use ndarray::{Array1, Array2};
+use ndarray::{Array1, Array2, ArrayView1, Axis};
 use vectro_lib::{Embedding, EmbeddingDataset};
-use vectro_lib::search::{SearchIndex, QuantizedIndex};
+use vectro_lib::search::{self, quant, SearchIndex, QuantizedIndex};
 use std::collections::HashMap;
 
 /// Python wrapper for Embedding
@@ -105,17 +109,48 @@ impl PyEmbeddingDataset {
     }
 }
 
+/// Scoring used by a `PySearchIndex`, chosen by name from Python
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum IndexMetric {
+    Cosine,
+    Euclidean,
+    Dot,
+}
+
+impl IndexMetric {
+    fn parse(name: &str) -> PyResult<Self> {
+        match name {
+            "cosine" => Ok(Self::Cosine),
+            "euclidean" => Ok(Self::Euclidean),
+            "dot" => Ok(Self::Dot),
+            other => Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "unknown metric '{}': expected 'cosine', 'euclidean' or 'dot'",
+                other
+            ))),
+        }
+    }
+}
+
 /// Python wrapper for SearchIndex
 #[pyclass]
 struct PySearchIndex {
     inner: SearchIndex,
     id_to_index: HashMap<String, usize>,
+    metric: IndexMetric,
+    // raw vectors for the euclidean and dot metrics; `inner` only keeps normalized rows
+    raw: Vec<Embedding>,
+    // `inner` row of each dataset position; `None` where the index skipped the vector
+    rows: Vec<Option<usize>>,
 }
 
 #[pymethods]
 impl PySearchIndex {
+    /// `metric` is "cosine" (default), "euclidean" or "dot". Euclidean scores are
+    /// L2 distances, nearest first; dot scores are raw dot products, largest first.
     #[staticmethod]
-    fn from_dataset(dataset: &PyEmbeddingDataset) -> PyResult<Self> {
+    #[pyo3(signature = (dataset, metric = "cosine"))]
+    fn from_dataset(dataset: &PyEmbeddingDataset, metric: &str) -> PyResult<Self> {
+        let metric = IndexMetric::parse(metric)?;
         let index = SearchIndex::from_dataset(&dataset.inner.embeddings);
         
         // Build ID->index mapping
@@ -123,72 +158,165 @@ impl PySearchIndex {
         for (idx, embedding) in dataset.inner.embeddings.iter().enumerate() {
             id_to_index.insert(embedding.id.clone(), idx);
         }
+        let raw = if metric == IndexMetric::Cosine { Vec::new() } else { dataset.inner.embeddings.clone() };
+        let rows = index_rows(&index, &dataset.inner.embeddings);
         
-        Ok(Self { inner: index, id_to_index })
+        Ok(Self { inner: index, id_to_index, metric, raw, rows })
     }
 
-    fn search_vector(&self, py: Python<'_>, query: PyReadonlyArray1<f32>, top_k: usize) -> PyResult<Py<PyTuple>> {
+    /// The metric name given to `from_dataset`.
+    #[getter]
+    fn metric(&self) -> &'static str {
+        match self.metric {
+            IndexMetric::Cosine => "cosine",
+            IndexMetric::Euclidean => "euclidean",
+            IndexMetric::Dot => "dot",
+        }
+    }
+
+    /// Returns (int64 indices, scores); `dtype` selects "float32" (default) or "float64" scores.
+    #[pyo3(signature = (query, top_k, dtype = None))]
+    fn search_vector(&self, py: Python<'_>, query: PyReadonlyArray1<f32>, top_k: usize, dtype: Option<&str>) -> PyResult<Py<PyTuple>> {
         let query_vec = query.as_array().to_vec();
-        let results = self.inner.top_k(&query_vec, top_k);
+        let results = self.top_k(&query_vec, top_k);
         
         let mut indices = Vec::new();
         let mut similarities = Vec::new();
         
         // The results are (id, similarity) pairs, we need to convert to indices
         for (id, similarity) in results {
-            // Find the index of this ID in the original dataset
-            // Note: This is inefficient but works for the demo
-            // In production, we'd want to store ID->index mapping
             if let Some(index) = self.find_id_index(id) {
-                indices.push(index);
+                indices.push(index as i64);
                 similarities.push(similarity);
             }
         }
         
-        let indices_array: &PyArray1<usize> = Array1::from(indices).into_pyarray(py);
-        let similarities_array: &PyArray1<f32> = Array1::from(similarities).into_pyarray(py);
-        
-        Ok(PyTuple::new(py, &[indices_array.as_ref(), similarities_array.as_ref()]).into())
+        Ok(results_tuple(py, indices, similarities, dtype)?.into())
     }
 
-    fn batch_search(&self, py: Python<'_>, queries: PyReadonlyArray2<f32>, top_k: usize) -> PyResult<Py<PyList>> {
+    /// One (int64 indices, float32 scores) tuple per query. Queries are rows of
+    /// `(n_queries, dim)`; pass `transpose=True` for a column-major
+    /// `(dim, n_queries)` matrix to read columns without a copy.
+    #[pyo3(signature = (queries, top_k, transpose = false))]
+    fn batch_search(&self, py: Python<'_>, queries: PyReadonlyArray2<f32>, top_k: usize, transpose: bool) -> PyResult<Py<PyList>> {
         let queries_array = queries.as_array();
+        let (query_axis, dim_axis) = if transpose { (Axis(1), Axis(0)) } else { (Axis(0), Axis(1)) };
+        let dim = self.inner.dim();
+        if !self.inner.is_empty() && queries_array.len_of(dim_axis) != dim {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "query dimension {} does not match index dimension {}",
+                queries_array.len_of(dim_axis),
+                dim
+            )));
+        }
         let mut all_results = Vec::new();
         
-        for query_row in queries_array.outer_iter() {
+        for query_row in queries_array.axis_iter(query_axis) {
             let query_vec = query_row.to_vec();
-            let results = self.inner.top_k(&query_vec, top_k);
+            let results = self.top_k(&query_vec, top_k);
             
             let mut indices = Vec::new();
             let mut similarities = Vec::new();
             
             for (id, similarity) in results {
                 if let Some(index) = self.find_id_index(id) {
-                    indices.push(index);
+                    indices.push(index as i64);
                     similarities.push(similarity);
                 }
             }
             
-            let indices_array: &PyArray1<usize> = Array1::from(indices).into_pyarray(py);
-            let similarities_array: &PyArray1<f32> = Array1::from(similarities).into_pyarray(py);
-            let result_tuple = PyTuple::new(py, &[indices_array.as_ref(), similarities_array.as_ref()]);
-            
-            all_results.push(result_tuple);
+            all_results.push(results_tuple(py, indices, similarities, None)?);
         }
         
         Ok(PyList::new(py, all_results).into())
     }
 
+    /// The index's stored vectors as an `(n, dim)` float32 array, for debugging
+    /// rankings. These are the unit-length vectors cosine scoring uses, not the
+    /// originals (see `PyEmbeddingDataset.get_vectors`); zero vectors stay all zeros.
+    /// Row `i` is dataset entry `i`, matching the indices searches return; entries
+    /// the index skipped for their dimension (see `skipped`) are all zeros.
+    /// The array is a copy, so writing to it does not affect the index.
+    fn normalized_vectors(&self, py: Python<'_>) -> PyResult<Py<PyArray2<f32>>> {
+        let dim = self.inner.dim();
+        let stored = self.inner.normalized_rows();
+        let mut array = Array2::<f32>::zeros((self.rows.len(), dim));
+        for (mut out, row) in array.rows_mut().into_iter().zip(&self.rows) {
+            if let Some(r) = row {
+                out.assign(&ArrayView1::from(&stored[r * dim..(r + 1) * dim]));
+            }
+        }
+        Ok(array.into_pyarray(py).to_owned())
+    }
+
+    /// Ids of the dataset entries left out for their dimension.
+    #[getter]
+    fn skipped(&self) -> Vec<String> {
+        self.inner.skipped().to_vec()
+    }
+
     fn __repr__(&self) -> String {
         // We can't access private fields, so use a simpler representation
-        format!("PySearchIndex")
+        format!("PySearchIndex(metric='{}')", self.metric())
     }
 }
 
+/// `SearchIndex` row of each of `embeddings` (the dataset it was built from), or
+/// `None` where the index skipped that vector for its dimension.
+fn index_rows(index: &SearchIndex, embeddings: &[Embedding]) -> Vec<Option<usize>> {
+    let mut next_row = 0;
+    embeddings
+        .iter()
+        .map(|e| {
+            (e.dim() == index.dim()).then(|| {
+                next_row += 1;
+                next_row - 1
+            })
+        })
+        .collect()
+}
+
 impl PySearchIndex {
     fn find_id_index(&self, target_id: &str) -> Option<usize> {
         self.id_to_index.get(target_id).copied()
     }
+
+    /// Best `k` under the index metric; a query of the wrong dimension finds nothing
+    fn top_k(&self, query: &[f32], k: usize) -> Vec<(&str, f32)> {
+        if self.metric != IndexMetric::Cosine && query.len() != self.inner.dim() {
+            return Vec::new();
+        }
+        match self.metric {
+            IndexMetric::Cosine => self.inner.top_k(query, k),
+            IndexMetric::Euclidean => search::top_k_with_metric(&self.raw, query, k, &search::Euclidean),
+            IndexMetric::Dot => search::top_k_with_metric(&self.raw, query, k, &search::Dot),
+        }
+    }
+}
+
+/// Build an `(indices, scores)` tuple with int64 indices and scores in the requested dtype
+fn results_tuple<'py>(py: Python<'py>, indices: Vec<i64>, similarities: Vec<f32>, dtype: Option<&str>) -> PyResult<&'py PyTuple> {
+    let indices_array: &PyArray1<i64> = Array1::from(indices).into_pyarray(py);
+    let similarities_array: &PyAny = match dtype.unwrap_or("float32") {
+        "float32" => Array1::from(similarities).into_pyarray(py).as_ref(),
+        "float64" => Array1::from_iter(similarities.into_iter().map(f64::from)).into_pyarray(py).as_ref(),
+        other => {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "unsupported score dtype '{}': expected 'float32' or 'float64'",
+                other
+            )))
+        }
+    };
+    Ok(PyTuple::new(py, [indices_array.as_ref(), similarities_array]))
+}
+
+/// `QuantizedIndex::from_dataset` raising ValueError for mixed dimensions; an
+/// empty dataset still gives an empty index.
+fn quantized_index(embeddings: &[Embedding]) -> PyResult<QuantizedIndex> {
+    if embeddings.is_empty() {
+        return Ok(QuantizedIndex::from_dataset(embeddings));
+    }
+    QuantizedIndex::try_from_dataset(embeddings).map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
 }
 
 /// Python wrapper for QuantizedIndex
@@ -202,7 +330,7 @@ struct PyQuantizedIndex {
 impl PyQuantizedIndex {
     #[staticmethod]
     fn from_dataset(dataset: &PyEmbeddingDataset) -> PyResult<Self> {
-        let index = QuantizedIndex::from_dataset(&dataset.inner.embeddings);
+        let index = quantized_index(&dataset.inner.embeddings)?;
         
         // Build ID->index mapping
         let mut id_to_index = HashMap::new();
@@ -213,6 +341,7 @@ impl PyQuantizedIndex {
         Ok(Self { inner: index, id_to_index })
     }
 
+    /// Returns (int64 indices, float32 scores), like `PySearchIndex.search_vector`.
     fn search_vector(&self, py: Python<'_>, query: PyReadonlyArray1<f32>, top_k: usize) -> PyResult<Py<PyTuple>> {
         let query_vec = query.as_array().to_vec();
         let results = self.inner.top_k(&query_vec, top_k);
@@ -222,15 +351,12 @@ impl PyQuantizedIndex {
         
         for (id, similarity) in results {
             if let Some(index) = self.find_id_index(id) {
-                indices.push(index);
+                indices.push(index as i64);
                 similarities.push(similarity);
             }
         }
         
-        let indices_array: &PyArray1<usize> = Array1::from(indices).into_pyarray(py);
-        let similarities_array: &PyArray1<f32> = Array1::from(similarities).into_pyarray(py);
-        
-        Ok(PyTuple::new(py, &[indices_array.as_ref(), similarities_array.as_ref()]).into())
+        Ok(results_tuple(py, indices, similarities, None)?.into())
     }
 
     fn compression_ratio(&self) -> f32 {
@@ -271,7 +397,7 @@ fn compress_embeddings(py: Python<'_>, vectors: PyReadonlyArray2<f32>, ids: Opti
     
     // Create both regular and quantized indices
     let search_index = SearchIndex::from_dataset(&dataset.embeddings);
-    let quantized_index = QuantizedIndex::from_dataset(&dataset.embeddings);
+    let quantized_index = quantized_index(&dataset.embeddings)?;
     
     // Build ID->index mapping
     let mut id_to_index = HashMap::new();
@@ -280,8 +406,11 @@ fn compress_embeddings(py: Python<'_>, vectors: PyReadonlyArray2<f32>, ids: Opti
     }
     
     let py_search_index = PySearchIndex { 
+        rows: index_rows(&search_index, &dataset.embeddings),
         inner: search_index, 
-        id_to_index: id_to_index.clone()
+        id_to_index: id_to_index.clone(),
+        metric: IndexMetric::Cosine,
+        raw: Vec::new(),
     };
     let py_quantized_index = PyQuantizedIndex { 
         inner: quantized_index, 
@@ -294,6 +423,48 @@ fn compress_embeddings(py: Python<'_>, vectors: PyReadonlyArray2<f32>, ids: Opti
     ]).into())
 }
 
+/// Per-dimension (min, max) ranges plus the u8 code matrix
+type QuantizedMatrix = (Vec<(f32, f32)>, Py<PyArray2<u8>>);
+
+/// Quantize a matrix per-dimension (min/max, u8). Returns ([(min, max), ...], codes)
+#[pyfunction]
+fn quantize_matrix(py: Python<'_>, vectors: PyReadonlyArray2<f32>) -> PyResult<QuantizedMatrix> {
+    let vectors_array = vectors.as_array();
+    let rows: Vec<Vec<f32>> = vectors_array.outer_iter().map(|r| r.to_vec()).collect();
+    let (tables, qvecs) = quant::try_quantize_dataset(&rows)
+        .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+
+    let mut codes = Array2::zeros((vectors_array.nrows(), vectors_array.ncols()));
+    for (i, qv) in qvecs.iter().enumerate() {
+        for (j, &b) in qv.iter().enumerate() {
+            codes[[i, j]] = b;
+        }
+    }
+
+    let ranges = tables.iter().map(|t| (t.min, t.max)).collect();
+    Ok((ranges, codes.into_pyarray(py).to_owned()))
+}
+
+/// Inverse of `quantize_matrix`: map u8 codes back to floats using per-dimension ranges
+#[pyfunction]
+fn dequantize_matrix(py: Python<'_>, tables: Vec<(f32, f32)>, codes: PyReadonlyArray2<u8>) -> PyResult<Py<PyArray2<f32>>> {
+    let codes_array = codes.as_array();
+    if codes_array.ncols() != tables.len() {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "codes have {} columns but {} quantization tables were given",
+            codes_array.ncols(),
+            tables.len()
+        )));
+    }
+    let tables: Vec<quant::QuantTable> = tables.into_iter().map(|(min, max)| quant::QuantTable::new(min, max)).collect();
+
+    let mut out = Array2::zeros(codes_array.dim());
+    for ((i, j), &b) in codes_array.indexed_iter() {
+        out[[i, j]] = tables[j].dequantize(b);
+    }
+    Ok(out.into_pyarray(py).to_owned())
+}
+
 /// Quality analysis utilities
 #[pyfunction]
 fn analyze_compression_quality(
@@ -334,14 +505,11 @@ fn analyze_compression_quality(
     Ok(analysis)
 }
 
-/// Performance benchmarking utilities
-#[pyfunction]
-fn benchmark_search_performance(
-    index: &PySearchIndex,
-    queries: PyReadonlyArray2<f32>,
-    top_k: usize,
-    num_runs: Option<usize>
-) -> PyResult<HashMap<String, f32>> {
+/// Time `search` over every query row `runs` times and summarize latency/throughput
+fn benchmark_queries<F>(queries: PyReadonlyArray2<f32>, num_runs: Option<usize>, search: F) -> HashMap<String, f32>
+where
+    F: Fn(&[f32]),
+{
     use std::time::Instant;
     
     let runs = num_runs.unwrap_or(10);
@@ -353,7 +521,7 @@ fn benchmark_search_performance(
         for query_row in queries_array.outer_iter() {
             let start = Instant::now();
             let query_vec = query_row.to_vec();
-            let _results = index.inner.top_k(&query_vec, top_k);
+            search(&query_vec);
             let duration = start.elapsed();
             total_time += duration.as_secs_f32() * 1000.0; // Convert to milliseconds
             successful_queries += 1;
@@ -378,7 +546,33 @@ fn benchmark_search_performance(
     benchmark.insert("successful_queries".to_string(), successful_queries as f32);
     benchmark.insert("total_runs".to_string(), (runs * queries_array.nrows()) as f32);
     
-    Ok(benchmark)
+    benchmark
+}
+
+/// Performance benchmarking utilities
+#[pyfunction]
+fn benchmark_search_performance(
+    index: &PySearchIndex,
+    queries: PyReadonlyArray2<f32>,
+    top_k: usize,
+    num_runs: Option<usize>
+) -> PyResult<HashMap<String, f32>> {
+    Ok(benchmark_queries(queries, num_runs, |q| {
+        let _results = index.top_k(q, top_k);
+    }))
+}
+
+/// Same metrics as `benchmark_search_performance`, for a quantized index
+#[pyfunction]
+fn benchmark_quantized_search_performance(
+    index: &PyQuantizedIndex,
+    queries: PyReadonlyArray2<f32>,
+    top_k: usize,
+    num_runs: Option<usize>
+) -> PyResult<HashMap<String, f32>> {
+    Ok(benchmark_queries(queries, num_runs, |q| {
+        let _results = index.inner.top_k(q, top_k);
+    }))
 }
 
 /// Main Python module
@@ -391,6 +585,9 @@ fn vectro_py(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(compress_embeddings, m)?)?;
     m.add_function(wrap_pyfunction!(analyze_compression_quality, m)?)?;
     m.add_function(wrap_pyfunction!(benchmark_search_performance, m)?)?;
+    m.add_function(wrap_pyfunction!(benchmark_quantized_search_performance, m)?)?;
+    m.add_function(wrap_pyfunction!(quantize_matrix, m)?)?;
+    m.add_function(wrap_pyfunction!(dequantize_matrix, m)?)?;
     
     // Add version info
     m.add("__version__", "1.1.0")?;